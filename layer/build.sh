@@ -41,9 +41,9 @@ fi
 if [ -n "$ZIG" ] && [ -f "$INTERCEPT_SRC" ]; then
   echo "Building DNS intercept .so with: $ZIG cc"
   "$ZIG" cc -shared -fPIC -o /tmp/dns-intercept-x86_64.so "$INTERCEPT_SRC" \
-    -target x86_64-linux-gnu -ldl
+    -target x86_64-linux-gnu -ldl -lpthread
   "$ZIG" cc -shared -fPIC -o /tmp/dns-intercept-aarch64.so "$INTERCEPT_SRC" \
-    -target aarch64-linux-gnu -ldl
+    -target aarch64-linux-gnu -ldl -lpthread
   HAS_INTERCEPT=true
 else
   echo "WARNING: zig not found or dns_intercept.c missing — denylist will not work"
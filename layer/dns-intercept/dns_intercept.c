@@ -1,33 +1,89 @@
 /**
  * LD_PRELOAD library for failure-lambda denylist.
  *
- * Intercepts libc's getaddrinfo() to block DNS resolution for hostnames
- * matching deny patterns. The proxy writes patterns (one POSIX Extended
- * Regular Expression per line) to /tmp/.failure-lambda-denylist. This
- * library reads the file on each getaddrinfo() call, matches the hostname,
- * and returns EAI_NONAME for matches (equivalent to NXDOMAIN).
+ * Intercepts libc's resolver entry points — getaddrinfo(), getaddrinfo_a(),
+ * gethostbyname(), gethostbyname2() and their _r variants — to block or
+ * delay DNS resolution for hostnames matching patterns written by the proxy
+ * to /tmp/.failure-lambda-denylist. This library compiles the file's rules
+ * when it changes and matches each lookup's hostname against them.
+ *
+ * File format (one rule per line, patterns are POSIX Extended Regular
+ * Expressions):
+ *   deny\tresult=<EAI_*>[\terrno=<n>]\t<pattern>
+ *                                      return the result (EAI_NONAME,
+ *                                      EAI_AGAIN, EAI_FAIL, or EAI_SYSTEM
+ *                                      with errno set)
+ *   rewrite\taddress=<ip>[\taddress=<ip>...]\t<pattern>
+ *                                      resolve to the given IP literals
+ *                                      instead (blackhole or stand-in)
+ *   delay\tmin=<ms>\tmax=<ms>\t<pattern>
+ *                                      sleep a random [min, max] ms, then
+ *                                      resolve normally
+ *   allow\t<pattern>                    resolve normally, overriding any
+ *                                      later deny or rewrite rule
+ *   <pattern>                          legacy format: deny with EAI_NONAME
+ * Structured lines start with an action and a tab; options between the
+ * action and the pattern are key=value fields, and unknown ones are ignored.
+ * A deny or rewrite rule with percentage=<n> applies to a matching lookup
+ * with n% probability, rolled on every call, so retries can succeed.
+ * The first matching allow, deny or rewrite rule decides the outcome, and the first
+ * matching delay rule applies on top, so a hostname can be both delayed and
+ * denied (a slow failure). The proxy writes allow rules first and ends an
+ * allowlist with a catch-all deny, so only allowed hostnames resolve.
+ *
+ * connect() interception:
+ *   Connections to IP literals, or to addresses resolved before the denylist
+ *   was written, never reach getaddrinfo(). connect() checks a second rules
+ *   file, /tmp/.failure-lambda-connect, whose patterns are CIDR ranges
+ *   (10.0.0.0/8, 2001:db8::/32, or a bare address):
+ *     refuse\t[ports=<p,p>\t]<cidr>     fail with ECONNREFUSED
+ *     reset\t[ports=<p,p>\t]<cidr>      fail with ECONNRESET
+ *     timeout\t[ports=<p,p>\t]<cidr>    connect to an unroutable TEST-NET
+ *                                      address instead, so the connection
+ *                                      hangs until the client (or the
+ *                                      kernel, with ETIMEDOUT) gives up
+ *     delay\tmin=<ms>\tmax=<ms>\t<cidr>  sleep, then connect normally
+ *   percentage=<n> works as for DNS rules. Only stream (TCP) sockets are
+ *   intercepted: connected UDP sockets, such as c-ares DNS queries, pass
+ *   through. Loopback destinations are never intercepted, so the runtime can
+ *   always reach the proxy. A delay blocks
+ *   the calling thread, which for event-loop runtimes (Node.js) is the loop.
  *
  * Communication:
- *   proxy writes patterns → /tmp/.failure-lambda-denylist (atomic: tmp+rename)
+ *   proxy writes rules → /tmp/.failure-lambda-denylist (atomic: tmp+rename)
  *   proxy removes file → denylist deactivated
  *
  * Performance:
- *   When denylist is inactive (no file): single stat() syscall (~1μs).
- *   When active: file read + regex compile per DNS lookup. Acceptable for
- *   a chaos engineering tool where denylist is used for testing.
+ *   When denylist is inactive (no file): single stat() syscall (~1μs), no
+ *   locking or allocation.
+ *   When active: the rules are parsed and their patterns compiled once, and
+ *   cached until the file's inode, mtime or size changes (the proxy's
+ *   tmp+rename always creates a new inode). Each lookup then costs a stat()
+ *   plus regexec() per rule. bench_dns_intercept.c measures both paths.
+ *   The connect rules file is not cached: it is re-read on every connect()
+ *   to a non-loopback TCP destination while it exists. CIDR rules need no
+ *   compiling and connections are far rarer than lookups.
  *
  * Thread safety:
- *   All state is stack-local (no mutable globals). Multiple threads calling
- *   getaddrinfo concurrently is safe.
+ *   The compiled-rules cache is guarded by a rwlock: lookups share a read
+ *   lock, and the first lookup to see a changed file recompiles under the
+ *   write lock. Everything else is stack-local, apart from a per-thread
+ *   random state for sampling delays. Multiple threads calling getaddrinfo
+ *   concurrently is safe.
  *
  * Runtime coverage:
  *   Works for any runtime using libc's getaddrinfo: Node.js (libuv),
  *   Python (socket), Java (JNI), Rust (tokio). Go's pure Go resolver
  *   bypasses libc — use GODEBUG=netdns=cgo to force the libc resolver.
  *
- * Note: getaddrinfo_a (the glibc async variant) is not intercepted. None
- * of the major Lambda runtimes use it — Node.js/libuv calls the synchronous
- * getaddrinfo in a thread pool, Python and Java use it synchronously via JNI.
+ * gethostbyname*() (older native modules, Ruby, some JNI code) follow the
+ * same rules: a deny sets h_errno (HOST_NOT_FOUND, TRY_AGAIN, NO_RECOVERY,
+ * or NETDB_INTERNAL with errno for EAI_SYSTEM) and a rewrite returns the
+ * addresses of the requested family. getaddrinfo_a() completes denied and
+ * rewritten requests before returning and submits the rest to glibc. Delays
+ * are applied in the calling thread for GAI_WAIT; a delayed GAI_NOWAIT batch
+ * is completed and submitted by a separate thread, so the call still returns
+ * at once and its requests stay EAI_INPROGRESS until the delay ends.
  *
  * Failure mode:
  *   If LD_PRELOAD fails to load (glibc version mismatch, etc.), the runtime
@@ -36,15 +92,35 @@
  */
 
 #define _GNU_SOURCE
+#include <arpa/inet.h>
 #include <dlfcn.h>
+#include <errno.h>
 #include <netdb.h>
+#include <pthread.h>
+#include <signal.h>
 #include <regex.h>
+#include <stdint.h>
 #include <stdio.h>
+#include <stdlib.h>
 #include <string.h>
+#include <sys/socket.h>
 #include <sys/stat.h>
+#include <time.h>
+#include <unistd.h>
 
 #define DENYLIST_PATH "/tmp/.failure-lambda-denylist"
+#define CONNECT_PATH "/tmp/.failure-lambda-connect"
+/* The proxy's config validation keeps rule lines within these limits. */
 #define MAX_LINE 512
+#define MAX_ADDRESSES 4
+#define MAX_ADDRESS_LEN 46 /* INET6_ADDRSTRLEN */
+#define MAX_CIDR_LEN 50
+/* Per-thread storage for gethostbyname() and gethostbyname2() results. */
+#define HOSTENT_BUFFER_LEN 2048
+
+/* TEST-NET-1 and the IPv6 documentation prefix: never routed. */
+#define BLACKHOLE_IPV4 "192.0.2.1"
+#define BLACKHOLE_IPV6 "2001:db8::1"
 
 typedef int (*getaddrinfo_fn)(const char *, const char *,
                               const struct addrinfo *,
@@ -52,25 +128,219 @@ typedef int (*getaddrinfo_fn)(const char *, const char *,
 
 static getaddrinfo_fn real_getaddrinfo = NULL;
 
+typedef struct hostent *(*gethostbyname_fn)(const char *);
+static gethostbyname_fn real_gethostbyname = NULL;
+
+typedef struct hostent *(*gethostbyname2_fn)(const char *, int);
+static gethostbyname2_fn real_gethostbyname2 = NULL;
+
+typedef int (*gethostbyname_r_fn)(const char *, struct hostent *, char *,
+                                  size_t, struct hostent **, int *);
+static gethostbyname_r_fn real_gethostbyname_r = NULL;
+
+typedef int (*gethostbyname2_r_fn)(const char *, int, struct hostent *,
+                                   char *, size_t, struct hostent **, int *);
+static gethostbyname2_r_fn real_gethostbyname2_r = NULL;
+
+typedef int (*getaddrinfo_a_fn)(int, struct gaicb **, int, struct sigevent *);
+static getaddrinfo_a_fn real_getaddrinfo_a = NULL;
+
+typedef int (*connect_fn)(int, const struct sockaddr *, socklen_t);
+static connect_fn real_connect = NULL;
+
+enum rule_action {
+    RULE_ALLOW,
+    RULE_DENY,
+    RULE_REWRITE,
+    RULE_DELAY,
+    RULE_REFUSE,
+    RULE_RESET,
+    RULE_TIMEOUT,
+};
+
+/** A parsed rules-file line. `pattern` points into the line buffer. */
+struct rule {
+    enum rule_action action;
+    int result;
+    int error_number;
+    long min_ms;
+    long max_ms;
+    int percentage;
+    int address_count;
+    const char *addresses[MAX_ADDRESSES];
+    const char *ports;
+    const char *pattern;
+};
+
 /**
- * Check whether hostname matches any deny pattern in the denylist file.
- * Returns 1 if denied, 0 if allowed or file doesn't exist.
+ * What the matching rules decided for a hostname. Addresses are copied out
+ * of the line buffer, which is reused for the next line.
  */
-static int is_denied(const char *hostname) {
-    /* Fast path: if the file doesn't exist, skip everything. */
-    struct stat st;
-    if (stat(DENYLIST_PATH, &st) != 0 || st.st_size == 0) {
-        return 0;
+struct verdict {
+    int allowed;
+    int denied;
+    int rewritten;
+    int result;
+    int error_number;
+    long delay_ms;
+    int address_count;
+    char addresses[MAX_ADDRESSES][MAX_ADDRESS_LEN];
+};
+
+/** Map a result name to its EAI_* code. Unknown names deny with EAI_NONAME. */
+static int parse_result(const char *name) {
+    if (strcmp(name, "EAI_AGAIN") == 0) {
+        return EAI_AGAIN;
+    }
+    if (strcmp(name, "EAI_FAIL") == 0) {
+        return EAI_FAIL;
+    }
+    if (strcmp(name, "EAI_SYSTEM") == 0) {
+        return EAI_SYSTEM;
+    }
+    return EAI_NONAME;
+}
+
+/**
+ * Apply a key=value option to a rule. Unknown keys are ignored so older
+ * libraries keep working with files written by a newer proxy.
+ */
+static void parse_option(const char *option, struct rule *rule) {
+    const char *eq = strchr(option, '=');
+    if (!eq) {
+        return;
+    }
+    size_t key_len = (size_t)(eq - option);
+    const char *value = eq + 1;
+
+    if (key_len == 6 && strncmp(option, "result", 6) == 0) {
+        rule->result = parse_result(value);
+    } else if (key_len == 5 && strncmp(option, "errno", 5) == 0) {
+        rule->error_number = (int)strtol(value, NULL, 10);
+    } else if (key_len == 7 && strncmp(option, "address", 7) == 0) {
+        if (rule->address_count < MAX_ADDRESSES) {
+            rule->addresses[rule->address_count++] = value;
+        }
+    } else if (key_len == 10 && strncmp(option, "percentage", 10) == 0) {
+        rule->percentage = (int)strtol(value, NULL, 10);
+    } else if (key_len == 3 && strncmp(option, "min", 3) == 0) {
+        rule->min_ms = strtol(value, NULL, 10);
+    } else if (key_len == 3 && strncmp(option, "max", 3) == 0) {
+        rule->max_ms = strtol(value, NULL, 10);
+    } else if (key_len == 5 && strncmp(option, "ports", 5) == 0) {
+        rule->ports = value;
+    }
+}
+
+/**
+ * Parse a rules-file line in place. A line without a known action prefix is
+ * a bare pattern, denied with EAI_NONAME.
+ */
+static void parse_rule(char *line, struct rule *rule) {
+    memset(rule, 0, sizeof(*rule));
+    rule->action = RULE_DENY;
+    rule->result = EAI_NONAME;
+    rule->error_number = EIO;
+    rule->percentage = 100;
+    rule->pattern = line;
+
+    char *tab = strchr(line, '\t');
+    if (!tab) {
+        return;
+    }
+
+    size_t action_len = (size_t)(tab - line);
+    if (action_len == 5 && strncmp(line, "allow", 5) == 0) {
+        rule->action = RULE_ALLOW;
+    } else if (action_len == 4 && strncmp(line, "deny", 4) == 0) {
+        rule->action = RULE_DENY;
+    } else if (action_len == 7 && strncmp(line, "rewrite", 7) == 0) {
+        rule->action = RULE_REWRITE;
+    } else if (action_len == 5 && strncmp(line, "delay", 5) == 0) {
+        rule->action = RULE_DELAY;
+    } else if (action_len == 6 && strncmp(line, "refuse", 6) == 0) {
+        rule->action = RULE_REFUSE;
+    } else if (action_len == 5 && strncmp(line, "reset", 5) == 0) {
+        rule->action = RULE_RESET;
+    } else if (action_len == 7 && strncmp(line, "timeout", 7) == 0) {
+        rule->action = RULE_TIMEOUT;
+    } else {
+        return;
+    }
+
+    char *field = tab + 1;
+    while ((tab = strchr(field, '\t')) != NULL) {
+        *tab = '\0';
+        parse_option(field, rule);
+        field = tab + 1;
+    }
+    rule->pattern = field;
+}
+
+/** A denylist rule with its compiled pattern. `rule` points into `line`. */
+struct compiled_rule {
+    struct rule rule;
+    char *line;
+    regex_t regex;
+    int compiled;
+};
+
+/**
+ * Compiled rules of the denylist file, identified by the file's device,
+ * inode, mtime and size when it was loaded.
+ */
+static struct {
+    pthread_rwlock_t lock;
+    int loaded;
+    dev_t dev;
+    ino_t ino;
+    struct timespec mtime;
+    off_t size;
+    struct compiled_rule *rules;
+    size_t count;
+} rule_cache = {.lock = PTHREAD_RWLOCK_INITIALIZER};
+
+/** Returns 1 if the cache was loaded from the file described by st. */
+static int cache_matches(const struct stat *st) {
+    return rule_cache.loaded && rule_cache.dev == st->st_dev &&
+           rule_cache.ino == st->st_ino &&
+           rule_cache.mtime.tv_sec == st->st_mtim.tv_sec &&
+           rule_cache.mtime.tv_nsec == st->st_mtim.tv_nsec &&
+           rule_cache.size == st->st_size;
+}
+
+static void free_cached_rules(void) {
+    for (size_t i = 0; i < rule_cache.count; i++) {
+        if (rule_cache.rules[i].compiled) {
+            regfree(&rule_cache.rules[i].regex);
+        }
+        free(rule_cache.rules[i].line);
     }
+    free(rule_cache.rules);
+    rule_cache.rules = NULL;
+    rule_cache.count = 0;
+}
+
+/**
+ * Parse and compile the denylist file into the cache. Caller holds the
+ * write lock. If the file can't be read, the cache is left empty (but keyed
+ * to st) so lookups resolve normally until the file changes again.
+ */
+static void load_rule_cache(const struct stat *st) {
+    free_cached_rules();
+    rule_cache.loaded = 1;
+    rule_cache.dev = st->st_dev;
+    rule_cache.ino = st->st_ino;
+    rule_cache.mtime = st->st_mtim;
+    rule_cache.size = st->st_size;
 
     FILE *f = fopen(DENYLIST_PATH, "r");
     if (!f) {
-        return 0;
+        return;
     }
 
-    int denied = 0;
+    size_t capacity = 0;
     char line[MAX_LINE];
-
     while (fgets(line, sizeof(line), f) != NULL) {
         /* Strip trailing newline */
         size_t len = strlen(line);
@@ -81,21 +351,182 @@ static int is_denied(const char *hostname) {
             continue;
         }
 
-        regex_t re;
-        if (regcomp(&re, line, REG_EXTENDED | REG_NOSUB) == 0) {
-            if (regexec(&re, hostname, 0, NULL, 0) == 0) {
-                denied = 1;
-            }
-            regfree(&re);
-            if (denied) {
+        if (rule_cache.count == capacity) {
+            size_t new_capacity = capacity ? capacity * 2 : 16;
+            struct compiled_rule *grown = realloc(
+                rule_cache.rules, new_capacity * sizeof(*grown));
+            if (!grown) {
                 break;
             }
+            rule_cache.rules = grown;
+            capacity = new_capacity;
+        }
+
+        struct compiled_rule *entry = &rule_cache.rules[rule_cache.count];
+        entry->line = strdup(line);
+        if (!entry->line) {
+            break;
         }
-        /* Invalid regex: skip silently (same as TypeScript library). */
+        parse_rule(entry->line, &entry->rule);
+        /* Invalid regex: never matches (same as TypeScript library). */
+        entry->compiled = regcomp(&entry->regex, entry->rule.pattern,
+                                  REG_EXTENDED | REG_NOSUB) == 0;
+        rule_cache.count++;
     }
 
     fclose(f);
-    return denied;
+}
+
+/** Returns 1 if hostname matches the rule's pattern. */
+static int matches_rule(const struct compiled_rule *entry,
+                        const char *hostname) {
+    return entry->compiled &&
+           regexec(&entry->regex, hostname, 0, NULL, 0) == 0;
+}
+
+/** Per-thread xorshift64* generator, seeded lazily from the clock. */
+static uint64_t next_random(void) {
+    static __thread uint64_t state = 0;
+    if (state == 0) {
+        struct timespec ts;
+        clock_gettime(CLOCK_MONOTONIC, &ts);
+        state = ((uint64_t)ts.tv_sec * 1000000000ULL + (uint64_t)ts.tv_nsec) ^
+                (uint64_t)(uintptr_t)&ts;
+        if (state == 0) {
+            state = 0x9e3779b97f4a7c15ULL;
+        }
+    }
+    state ^= state >> 12;
+    state ^= state << 25;
+    state ^= state >> 27;
+    return state * 0x2545f4914f6cdd1dULL;
+}
+
+/** Sample a delay in [min_ms, max_ms]. */
+static long sample_delay(const struct rule *rule) {
+    if (rule->max_ms <= rule->min_ms) {
+        return rule->min_ms;
+    }
+    uint64_t span = (uint64_t)(rule->max_ms - rule->min_ms) + 1;
+    return rule->min_ms + (long)(next_random() % span);
+}
+
+/** Roll the rule's per-lookup percentage. */
+static int roll_applies(const struct rule *rule) {
+    if (rule->percentage >= 100) {
+        return 1;
+    }
+    return (int)(next_random() % 100) < rule->percentage;
+}
+
+static void sleep_ms(long ms) {
+    struct timespec ts = {ms / 1000, (ms % 1000) * 1000000L};
+    while (nanosleep(&ts, &ts) != 0 && errno == EINTR) {
+    }
+}
+
+/**
+ * Evaluate the rules file against hostname. Leaves the verdict empty
+ * (not denied, no delay) if the file doesn't exist.
+ */
+static void evaluate_rules(const char *hostname, struct verdict *verdict) {
+    memset(verdict, 0, sizeof(*verdict));
+
+    /* Fast path: if the file doesn't exist, skip everything. */
+    struct stat st;
+    if (stat(DENYLIST_PATH, &st) != 0 || st.st_size == 0) {
+        return;
+    }
+
+    pthread_rwlock_rdlock(&rule_cache.lock);
+    if (!cache_matches(&st)) {
+        pthread_rwlock_unlock(&rule_cache.lock);
+        pthread_rwlock_wrlock(&rule_cache.lock);
+        /* Another thread may have reloaded while we waited. */
+        if (!cache_matches(&st)) {
+            load_rule_cache(&st);
+        }
+        pthread_rwlock_unlock(&rule_cache.lock);
+        pthread_rwlock_rdlock(&rule_cache.lock);
+    }
+
+    int delay_matched = 0;
+    for (size_t i = 0; i < rule_cache.count; i++) {
+        const struct compiled_rule *entry = &rule_cache.rules[i];
+        const struct rule *rule = &entry->rule;
+
+        int decided = verdict->allowed || verdict->denied || verdict->rewritten;
+        if (rule->action == RULE_ALLOW && !decided &&
+            matches_rule(entry, hostname)) {
+            verdict->allowed = 1;
+        } else if (rule->action == RULE_DENY && !decided &&
+                   matches_rule(entry, hostname) && roll_applies(rule)) {
+            verdict->denied = 1;
+            verdict->result = rule->result;
+            verdict->error_number = rule->error_number;
+        } else if (rule->action == RULE_REWRITE && !decided &&
+                   matches_rule(entry, hostname) && roll_applies(rule)) {
+            verdict->rewritten = 1;
+            for (int a = 0; a < rule->address_count; a++) {
+                snprintf(verdict->addresses[a], MAX_ADDRESS_LEN, "%s",
+                         rule->addresses[a]);
+            }
+            verdict->address_count = rule->address_count;
+        } else if (rule->action == RULE_DELAY && !delay_matched &&
+                   matches_rule(entry, hostname)) {
+            delay_matched = 1;
+            verdict->delay_ms = sample_delay(rule);
+        }
+
+        if ((verdict->allowed || verdict->denied || verdict->rewritten) &&
+            delay_matched) {
+            break;
+        }
+    }
+
+    pthread_rwlock_unlock(&rule_cache.lock);
+}
+
+/**
+ * Resolve to the verdict's addresses instead of the hostname. Each address
+ * goes through the real getaddrinfo as a numeric host, so the results honour
+ * the caller's hints (family, socket type, service) and are allocated the
+ * way freeaddrinfo() expects. Addresses of a family the caller excluded are
+ * skipped; if none are left, the last error is returned.
+ */
+static int resolve_rewrite(const struct verdict *verdict, const char *service,
+                           const struct addrinfo *hints,
+                           struct addrinfo **res) {
+    struct addrinfo numeric_hints;
+    memset(&numeric_hints, 0, sizeof(numeric_hints));
+    if (hints) {
+        numeric_hints = *hints;
+    }
+    numeric_hints.ai_flags |= AI_NUMERICHOST;
+
+    struct addrinfo *head = NULL;
+    struct addrinfo **tail = &head;
+    int last_error = EAI_NONAME;
+
+    for (int i = 0; i < verdict->address_count; i++) {
+        struct addrinfo *list = NULL;
+        int rc = real_getaddrinfo(verdict->addresses[i], service,
+                                  &numeric_hints, &list);
+        if (rc != 0) {
+            last_error = rc;
+            continue;
+        }
+        *tail = list;
+        while (*tail) {
+            tail = &(*tail)->ai_next;
+        }
+    }
+
+    if (!head) {
+        return last_error;
+    }
+    *res = head;
+    return 0;
 }
 
 int getaddrinfo(const char *node, const char *service,
@@ -108,9 +539,674 @@ int getaddrinfo(const char *node, const char *service,
         }
     }
 
-    if (node != NULL && node[0] != '\0' && is_denied(node)) {
-        return EAI_NONAME;
+    if (node != NULL && node[0] != '\0') {
+        struct verdict verdict;
+        evaluate_rules(node, &verdict);
+        if (verdict.delay_ms > 0) {
+            sleep_ms(verdict.delay_ms);
+        }
+        if (verdict.denied) {
+            if (verdict.result == EAI_SYSTEM) {
+                errno = verdict.error_number;
+            }
+            return verdict.result;
+        }
+        if (verdict.rewritten) {
+            return resolve_rewrite(&verdict, service, hints, res);
+        }
     }
 
     return real_getaddrinfo(node, service, hints, res);
 }
+
+/** Map a deny verdict to the h_errno reported by gethostbyname*(). */
+static int deny_h_errno(const struct verdict *verdict) {
+    switch (verdict->result) {
+    case EAI_AGAIN:
+        return TRY_AGAIN;
+    case EAI_FAIL:
+        return NO_RECOVERY;
+    case EAI_SYSTEM:
+        return NETDB_INTERNAL;
+    default:
+        return HOST_NOT_FOUND;
+    }
+}
+
+/**
+ * Build a hostent for a rewritten lookup in the caller's buffer, keeping
+ * only the verdict's addresses of family af. Returns 0, or ERANGE if the
+ * buffer is too small.
+ */
+static int fill_hostent(const char *name, int af,
+                        const struct verdict *verdict, struct hostent *ret,
+                        char *buf, size_t buflen, struct hostent **result,
+                        int *h_errnop) {
+    size_t address_len = af == AF_INET6 ? 16 : 4;
+    unsigned char addresses[MAX_ADDRESSES][16];
+    int count = 0;
+    for (int i = 0; i < verdict->address_count; i++) {
+        if (inet_pton(af, verdict->addresses[i], addresses[count]) == 1) {
+            count++;
+        }
+    }
+
+    *result = NULL;
+    if (count == 0) {
+        *h_errnop = NO_DATA;
+        return 0;
+    }
+
+    /* Pointer arrays first, so they're aligned once buf is. */
+    size_t padding = (size_t)(-(uintptr_t)buf & (sizeof(char *) - 1));
+    size_t name_len = strlen(name) + 1;
+    size_t needed = padding + (size_t)(count + 2) * sizeof(char *) +
+                    (size_t)count * address_len + name_len;
+    if (buflen < needed) {
+        *h_errnop = NETDB_INTERNAL;
+        errno = ERANGE;
+        return ERANGE;
+    }
+
+    char **address_list = (char **)(void *)(buf + padding);
+    char **aliases = address_list + count + 1;
+    char *p = (char *)(aliases + 1);
+    for (int i = 0; i < count; i++) {
+        memcpy(p, addresses[i], address_len);
+        address_list[i] = p;
+        p += address_len;
+    }
+    address_list[count] = NULL;
+    aliases[0] = NULL;
+    memcpy(p, name, name_len);
+
+    ret->h_name = p;
+    ret->h_aliases = aliases;
+    ret->h_addrtype = af;
+    ret->h_length = (int)address_len;
+    ret->h_addr_list = address_list;
+    *result = ret;
+    *h_errnop = NETDB_SUCCESS;
+    return 0;
+}
+
+/**
+ * Apply the rules to a gethostbyname*() lookup. Returns 1 if they decided
+ * the outcome, with the function's return value in *rc, or 0 to resolve
+ * normally.
+ */
+static int intercept_hostent(const char *name, int af, struct hostent *ret,
+                             char *buf, size_t buflen,
+                             struct hostent **result, int *h_errnop,
+                             int *rc) {
+    if (name == NULL || name[0] == '\0') {
+        return 0;
+    }
+
+    struct verdict verdict;
+    evaluate_rules(name, &verdict);
+    if (verdict.delay_ms > 0) {
+        sleep_ms(verdict.delay_ms);
+    }
+    if (verdict.denied) {
+        *result = NULL;
+        *h_errnop = deny_h_errno(&verdict);
+        *rc = 0;
+        if (verdict.result == EAI_SYSTEM) {
+            errno = verdict.error_number;
+            *rc = verdict.error_number;
+        }
+        return 1;
+    }
+    if (verdict.rewritten) {
+        *rc = fill_hostent(name, af, &verdict, ret, buf, buflen, result,
+                           h_errnop);
+        return 1;
+    }
+    return 0;
+}
+
+static __thread struct hostent host_entry;
+static __thread char host_buffer[HOSTENT_BUFFER_LEN];
+
+struct hostent *gethostbyname(const char *name) {
+    if (!real_gethostbyname) {
+        real_gethostbyname =
+            (gethostbyname_fn)dlsym(RTLD_NEXT, "gethostbyname");
+        if (!real_gethostbyname) {
+            h_errno = NO_RECOVERY;
+            return NULL;
+        }
+    }
+
+    struct hostent *result;
+    int rc;
+    if (intercept_hostent(name, AF_INET, &host_entry, host_buffer,
+                          sizeof(host_buffer), &result, &h_errno, &rc)) {
+        return result;
+    }
+    return real_gethostbyname(name);
+}
+
+struct hostent *gethostbyname2(const char *name, int af) {
+    if (!real_gethostbyname2) {
+        real_gethostbyname2 =
+            (gethostbyname2_fn)dlsym(RTLD_NEXT, "gethostbyname2");
+        if (!real_gethostbyname2) {
+            h_errno = NO_RECOVERY;
+            return NULL;
+        }
+    }
+
+    struct hostent *result;
+    int rc;
+    if (intercept_hostent(name, af, &host_entry, host_buffer,
+                          sizeof(host_buffer), &result, &h_errno, &rc)) {
+        return result;
+    }
+    return real_gethostbyname2(name, af);
+}
+
+int gethostbyname_r(const char *name, struct hostent *ret, char *buf,
+                    size_t buflen, struct hostent **result, int *h_errnop) {
+    if (!real_gethostbyname_r) {
+        real_gethostbyname_r =
+            (gethostbyname_r_fn)dlsym(RTLD_NEXT, "gethostbyname_r");
+        if (!real_gethostbyname_r) {
+            *result = NULL;
+            *h_errnop = NO_RECOVERY;
+            return ENOSYS;
+        }
+    }
+
+    int rc;
+    if (intercept_hostent(name, AF_INET, ret, buf, buflen, result, h_errnop,
+                          &rc)) {
+        return rc;
+    }
+    return real_gethostbyname_r(name, ret, buf, buflen, result, h_errnop);
+}
+
+int gethostbyname2_r(const char *name, int af, struct hostent *ret,
+                     char *buf, size_t buflen, struct hostent **result,
+                     int *h_errnop) {
+    if (!real_gethostbyname2_r) {
+        real_gethostbyname2_r =
+            (gethostbyname2_r_fn)dlsym(RTLD_NEXT, "gethostbyname2_r");
+        if (!real_gethostbyname2_r) {
+            *result = NULL;
+            *h_errnop = NO_RECOVERY;
+            return ENOSYS;
+        }
+    }
+
+    int rc;
+    if (intercept_hostent(name, af, ret, buf, buflen, result, h_errnop,
+                          &rc)) {
+        return rc;
+    }
+    return real_gethostbyname2_r(name, af, ret, buf, buflen, result,
+                                 h_errnop);
+}
+
+static void *run_notification(void *arg) {
+    struct sigevent *sevp = arg;
+    sevp->sigev_notify_function(sevp->sigev_value);
+    free(sevp);
+    return NULL;
+}
+
+/**
+ * Deliver a getaddrinfo_a() completion notification ourselves, for a
+ * GAI_NOWAIT batch whose requests the rules all decided.
+ */
+static void notify_completion(const struct sigevent *sevp) {
+    if (sevp->sigev_notify == SIGEV_SIGNAL) {
+        sigqueue(getpid(), sevp->sigev_signo, sevp->sigev_value);
+    } else if (sevp->sigev_notify == SIGEV_THREAD) {
+        struct sigevent *copy = malloc(sizeof(*copy));
+        if (!copy) {
+            return;
+        }
+        *copy = *sevp;
+        pthread_t thread;
+        if (pthread_create(&thread, sevp->sigev_notify_attributes,
+                           run_notification, copy) != 0) {
+            free(copy);
+            return;
+        }
+        pthread_detach(thread);
+    }
+}
+
+/**
+ * Complete a request the rules decided (denied or rewritten) and return 1, or
+ * return 0 for one glibc should resolve.
+ *
+ * `__return` is a private glibc field: gai_error() returns it as-is, and
+ * EAI_INPROGRESS there is what marks a request as still running. Writing it
+ * directly relies on that implementation detail.
+ */
+static int complete_decided(struct gaicb *req, const struct verdict *verdict) {
+    if (verdict->denied) {
+        req->ar_result = NULL;
+        req->__return = verdict->result;
+        return 1;
+    }
+    if (verdict->rewritten) {
+        req->ar_result = NULL;
+        req->__return = resolve_rewrite(verdict, req->ar_service,
+                                        req->ar_request, &req->ar_result);
+        return 1;
+    }
+    return 0;
+}
+
+/**
+ * Complete the decided requests in `list` and submit the rest to glibc,
+ * compacting `list` to the submitted ones and storing their count in
+ * `pending_count`. If there are none, a GAI_NOWAIT notification is delivered
+ * here instead.
+ */
+static int submit_batch(int mode, struct gaicb *list[], int nitems,
+                        const struct verdict *verdicts,
+                        struct sigevent *sevp, int *pending_count) {
+    *pending_count = 0;
+    for (int i = 0; i < nitems; i++) {
+        if (list[i] == NULL || !complete_decided(list[i], &verdicts[i])) {
+            list[(*pending_count)++] = list[i];
+        }
+    }
+
+    if (*pending_count > 0) {
+        return real_getaddrinfo_a(mode, list, *pending_count, sevp);
+    }
+    if (mode == GAI_NOWAIT && sevp != NULL) {
+        notify_completion(sevp);
+    }
+    return 0;
+}
+
+/** A GAI_NOWAIT batch held back for a delay rule. */
+struct delayed_batch {
+    long delay_ms;
+    int nitems;
+    int has_sigevent;
+    struct sigevent sev;
+    struct gaicb **list;
+    struct verdict *verdicts;
+};
+
+/**
+ * Submission thread for a delayed GAI_NOWAIT batch, so the caller returns
+ * immediately as the async contract requires. Its requests stay
+ * EAI_INPROGRESS until the delay ends.
+ */
+static void *run_delayed_batch(void *arg) {
+    struct delayed_batch *batch = arg;
+    sleep_ms(batch->delay_ms);
+    int pending_count;
+    int rc = submit_batch(GAI_NOWAIT, batch->list, batch->nitems,
+                          batch->verdicts,
+                          batch->has_sigevent ? &batch->sev : NULL,
+                          &pending_count);
+    if (rc != 0) {
+        /* The caller has already returned, so the failure can only be
+         * reported through the requests. glibc overwrites this again for
+         * any it did enqueue, once they complete. */
+        for (int i = 0; i < pending_count; i++) {
+            if (batch->list[i] != NULL) {
+                batch->list[i]->__return = rc;
+            }
+        }
+    }
+    free(batch->list);
+    free(batch->verdicts);
+    free(batch);
+    return NULL;
+}
+
+/**
+ * Hand a GAI_NOWAIT batch to a delay thread. Takes ownership of `verdicts`
+ * on success; returns 0 (owning nothing) if the thread can't be started.
+ */
+static int start_delayed_batch(struct gaicb *list[], int nitems,
+                               struct sigevent *sevp,
+                               struct verdict *verdicts, long delay_ms) {
+    struct delayed_batch *batch = calloc(1, sizeof(*batch));
+    struct gaicb **copy = malloc((size_t)nitems * sizeof(*copy));
+    if (!batch || !copy) {
+        free(batch);
+        free(copy);
+        return 0;
+    }
+    memcpy(copy, list, (size_t)nitems * sizeof(*copy));
+    batch->delay_ms = delay_ms;
+    batch->nitems = nitems;
+    batch->list = copy;
+    batch->verdicts = verdicts;
+    if (sevp != NULL) {
+        batch->has_sigevent = 1;
+        batch->sev = *sevp;
+    }
+
+    /* Until the thread submits them, the requests must read as running */
+    for (int i = 0; i < nitems; i++) {
+        if (list[i] != NULL) {
+            list[i]->__return = EAI_INPROGRESS;
+        }
+    }
+
+    pthread_t thread;
+    if (pthread_create(&thread, NULL, run_delayed_batch, batch) != 0) {
+        free(copy);
+        free(batch);
+        return 0;
+    }
+    pthread_detach(thread);
+    return 1;
+}
+
+int getaddrinfo_a(int mode, struct gaicb *list[], int nitems,
+                  struct sigevent *sevp) {
+    if (!real_getaddrinfo_a) {
+        real_getaddrinfo_a =
+            (getaddrinfo_a_fn)dlsym(RTLD_NEXT, "getaddrinfo_a");
+        if (!real_getaddrinfo_a) {
+            return EAI_SYSTEM;
+        }
+    }
+    if (!real_getaddrinfo) {
+        real_getaddrinfo = (getaddrinfo_fn)dlsym(RTLD_NEXT, "getaddrinfo");
+        if (!real_getaddrinfo) {
+            return EAI_SYSTEM;
+        }
+    }
+    if (nitems <= 0) {
+        return real_getaddrinfo_a(mode, list, nitems, sevp);
+    }
+
+    struct verdict *verdicts = malloc((size_t)nitems * sizeof(*verdicts));
+    if (!verdicts) {
+        return real_getaddrinfo_a(mode, list, nitems, sevp);
+    }
+
+    long delay_ms = 0;
+    for (int i = 0; i < nitems; i++) {
+        struct gaicb *req = list[i];
+        if (req == NULL || req->ar_name == NULL || req->ar_name[0] == '\0') {
+            memset(&verdicts[i], 0, sizeof(verdicts[i]));
+            continue;
+        }
+        evaluate_rules(req->ar_name, &verdicts[i]);
+        if (verdicts[i].delay_ms > delay_ms) {
+            delay_ms = verdicts[i].delay_ms;
+        }
+    }
+
+    /* GAI_NOWAIT callers must not block: the delay runs on its own thread.
+     * If that thread can't be started, fall back to delaying here. */
+    if (mode == GAI_NOWAIT && delay_ms > 0 &&
+        start_delayed_batch(list, nitems, sevp, verdicts, delay_ms)) {
+        return 0;
+    }
+    if (delay_ms > 0) {
+        sleep_ms(delay_ms);
+    }
+
+    /* Requests the rules decide are complete before this returns, so
+     * gai_error() and gai_suspend() see them as done. */
+    int rc = EAI_MEMORY;
+    struct gaicb **pending = malloc((size_t)nitems * sizeof(*pending));
+    if (pending) {
+        memcpy(pending, list, (size_t)nitems * sizeof(*pending));
+        int pending_count;
+        rc = submit_batch(mode, pending, nitems, verdicts, sevp,
+                          &pending_count);
+        free(pending);
+    }
+    free(verdicts);
+    return rc;
+}
+
+/**
+ * What the connect rules decided for a destination. `action` is RULE_ALLOW
+ * when no refuse, reset or timeout rule applies.
+ */
+struct connect_verdict {
+    enum rule_action action;
+    long delay_ms;
+};
+
+/**
+ * Extract the destination of a connect() call as a family and raw address
+ * bytes. IPv4-mapped IPv6 addresses (dual-stack sockets) are reported as
+ * IPv4. Returns 0 for non-IP sockets and loopback, which are never
+ * intercepted.
+ */
+static int connect_destination(const struct sockaddr *addr, socklen_t len,
+                               int *family, unsigned char address[16],
+                               unsigned int *port) {
+    if (addr == NULL) {
+        return 0;
+    }
+    if (addr->sa_family == AF_INET && len >= sizeof(struct sockaddr_in)) {
+        const struct sockaddr_in *in = (const struct sockaddr_in *)addr;
+        *family = AF_INET;
+        memcpy(address, &in->sin_addr, 4);
+        *port = ntohs(in->sin_port);
+    } else if (addr->sa_family == AF_INET6 &&
+               len >= sizeof(struct sockaddr_in6)) {
+        const struct sockaddr_in6 *in6 = (const struct sockaddr_in6 *)addr;
+        *port = ntohs(in6->sin6_port);
+        if (IN6_IS_ADDR_V4MAPPED(&in6->sin6_addr)) {
+            *family = AF_INET;
+            memcpy(address, &in6->sin6_addr.s6_addr[12], 4);
+        } else {
+            if (IN6_IS_ADDR_LOOPBACK(&in6->sin6_addr)) {
+                return 0;
+            }
+            *family = AF_INET6;
+            memcpy(address, &in6->sin6_addr, 16);
+            return 1;
+        }
+    } else {
+        return 0;
+    }
+    /* 127.0.0.0/8 */
+    return address[0] != 127;
+}
+
+/**
+ * Returns 1 for stream sockets. Datagram sockets are left alone: a fake
+ * ECONNREFUSED breaks connected UDP clients, and a timeout redirect would
+ * silently send their datagrams to TEST-NET.
+ */
+static int is_stream_socket(int sockfd) {
+    int type;
+    socklen_t len = sizeof(type);
+    if (getsockopt(sockfd, SOL_SOCKET, SO_TYPE, &type, &len) != 0) {
+        return 0;
+    }
+    return type == SOCK_STREAM;
+}
+
+/** Returns 1 if the address lies in the CIDR range. Invalid ranges never match. */
+static int matches_cidr(const char *cidr, int family,
+                        const unsigned char *address) {
+    char network_text[MAX_CIDR_LEN];
+    snprintf(network_text, sizeof(network_text), "%s", cidr);
+
+    int network_family = strchr(network_text, ':') ? AF_INET6 : AF_INET;
+    if (network_family != family) {
+        return 0;
+    }
+
+    int max_prefix = family == AF_INET ? 32 : 128;
+    int prefix = max_prefix;
+    char *slash = strchr(network_text, '/');
+    if (slash) {
+        *slash = '\0';
+        char *end;
+        prefix = (int)strtol(slash + 1, &end, 10);
+        if (end == slash + 1 || *end != '\0' || prefix < 0 ||
+            prefix > max_prefix) {
+            return 0;
+        }
+    }
+
+    unsigned char network[16];
+    if (inet_pton(family, network_text, network) != 1) {
+        return 0;
+    }
+
+    int full_bytes = prefix / 8;
+    int remaining_bits = prefix % 8;
+    if (memcmp(network, address, (size_t)full_bytes) != 0) {
+        return 0;
+    }
+    if (remaining_bits == 0) {
+        return 1;
+    }
+    unsigned char mask = (unsigned char)(0xff << (8 - remaining_bits));
+    return (network[full_bytes] & mask) == (address[full_bytes] & mask);
+}
+
+/** Returns 1 if port is in the comma-separated list. No list matches all. */
+static int matches_port(const char *ports, unsigned int port) {
+    if (ports == NULL) {
+        return 1;
+    }
+    const char *p = ports;
+    while (*p != '\0') {
+        char *end;
+        long candidate = strtol(p, &end, 10);
+        if (end == p) {
+            return 0;
+        }
+        if (candidate == (long)port) {
+            return 1;
+        }
+        p = *end == ',' ? end + 1 : end;
+    }
+    return 0;
+}
+
+/**
+ * Evaluate the connect rules file against a destination. As with DNS rules,
+ * the first matching refuse, reset or timeout rule decides and the first
+ * matching delay rule applies on top.
+ */
+static void evaluate_connect_rules(int family, const unsigned char *address,
+                                   unsigned int port,
+                                   struct connect_verdict *verdict) {
+    verdict->action = RULE_ALLOW;
+    verdict->delay_ms = 0;
+
+    struct stat st;
+    if (stat(CONNECT_PATH, &st) != 0 || st.st_size == 0) {
+        return;
+    }
+
+    FILE *f = fopen(CONNECT_PATH, "r");
+    if (!f) {
+        return;
+    }
+
+    int delay_matched = 0;
+    char line[MAX_LINE];
+
+    while (fgets(line, sizeof(line), f) != NULL) {
+        size_t len = strlen(line);
+        if (len > 0 && line[len - 1] == '\n') {
+            line[len - 1] = '\0';
+        }
+        if (line[0] == '\0') {
+            continue;
+        }
+
+        struct rule rule;
+        parse_rule(line, &rule);
+
+        /* Bare patterns are a DNS-file format; connect rules need an action. */
+        if (rule.pattern == line || !matches_port(rule.ports, port) ||
+            !matches_cidr(rule.pattern, family, address)) {
+            continue;
+        }
+
+        if (rule.action == RULE_DELAY) {
+            if (!delay_matched) {
+                delay_matched = 1;
+                verdict->delay_ms = sample_delay(&rule);
+            }
+        } else if ((rule.action == RULE_REFUSE || rule.action == RULE_RESET ||
+                    rule.action == RULE_TIMEOUT) &&
+                   verdict->action == RULE_ALLOW && roll_applies(&rule)) {
+            verdict->action = rule.action;
+        }
+
+        if (verdict->action != RULE_ALLOW && delay_matched) {
+            break;
+        }
+    }
+
+    fclose(f);
+}
+
+/**
+ * Connect to an unroutable address of the socket's family on the same port,
+ * so the connection attempt behaves like one to a host that drops packets.
+ */
+static int connect_blackhole(int sockfd, const struct sockaddr *addr) {
+    if (addr->sa_family == AF_INET) {
+        struct sockaddr_in blackhole = *(const struct sockaddr_in *)addr;
+        inet_pton(AF_INET, BLACKHOLE_IPV4, &blackhole.sin_addr);
+        return real_connect(sockfd, (const struct sockaddr *)&blackhole,
+                            sizeof(blackhole));
+    }
+
+    struct sockaddr_in6 blackhole = *(const struct sockaddr_in6 *)addr;
+    if (IN6_IS_ADDR_V4MAPPED(&blackhole.sin6_addr)) {
+        inet_pton(AF_INET, BLACKHOLE_IPV4, &blackhole.sin6_addr.s6_addr[12]);
+    } else {
+        inet_pton(AF_INET6, BLACKHOLE_IPV6, &blackhole.sin6_addr);
+    }
+    return real_connect(sockfd, (const struct sockaddr *)&blackhole,
+                        sizeof(blackhole));
+}
+
+int connect(int sockfd, const struct sockaddr *addr, socklen_t addrlen) {
+    if (!real_connect) {
+        real_connect = (connect_fn)dlsym(RTLD_NEXT, "connect");
+        if (!real_connect) {
+            errno = ENOSYS;
+            return -1;
+        }
+    }
+
+    int family;
+    unsigned char address[16];
+    unsigned int port;
+    if (connect_destination(addr, addrlen, &family, address, &port) &&
+        is_stream_socket(sockfd)) {
+        struct connect_verdict verdict;
+        evaluate_connect_rules(family, address, port, &verdict);
+        if (verdict.delay_ms > 0) {
+            sleep_ms(verdict.delay_ms);
+        }
+        switch (verdict.action) {
+        case RULE_REFUSE:
+            errno = ECONNREFUSED;
+            return -1;
+        case RULE_RESET:
+            errno = ECONNRESET;
+            return -1;
+        case RULE_TIMEOUT:
+            return connect_blackhole(sockfd, addr);
+        default:
+            break;
+        }
+    }
+
+    return real_connect(sockfd, addr, addrlen);
+}
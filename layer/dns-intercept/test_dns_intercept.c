@@ -0,0 +1,433 @@
+/**
+ * Tests for dns_intercept.so. Run under LD_PRELOAD (see the Makefile's
+ * `test` target): writes its own rules files, then checks that every
+ * intercepted entry point denies, rewrites and delays matching lookups,
+ * that allow rules and per-lookup percentages decide as documented, that a
+ * replaced rules file takes effect, and that connect() refuses, resets,
+ * blackholes and delays matching destinations.
+ *
+ * Hostnames are chosen so that only the library can produce the expected
+ * results (a NO_RECOVERY/EAI_FAIL deny, or a TEST-NET rewrite), so a run
+ * without the preload fails instead of passing vacuously. No test needs
+ * network access.
+ */
+
+#define _GNU_SOURCE
+#include <arpa/inet.h>
+#include <errno.h>
+#include <netdb.h>
+#include <netinet/in.h>
+#include <poll.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <time.h>
+#include <unistd.h>
+
+#define DENYLIST_PATH "/tmp/.failure-lambda-denylist"
+#define CONNECT_PATH "/tmp/.failure-lambda-connect"
+
+static int failures = 0;
+
+#define CHECK(cond, ...)                                                   \
+    do {                                                                   \
+        if (cond) {                                                        \
+            printf("ok    ");                                              \
+        } else {                                                           \
+            printf("FAIL  ");                                              \
+            failures++;                                                    \
+        }                                                                  \
+        printf(__VA_ARGS__);                                               \
+        printf("\n");                                                      \
+    } while (0)
+
+static void write_file(const char *path, const char *contents) {
+    FILE *f = fopen(path, "w");
+    if (!f) {
+        perror(path);
+        exit(2);
+    }
+    fputs(contents, f);
+    fclose(f);
+}
+
+static long elapsed_ms(const struct timespec *start) {
+    struct timespec now;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+    return (now.tv_sec - start->tv_sec) * 1000 +
+           (now.tv_nsec - start->tv_nsec) / 1000000;
+}
+
+/** Returns 1 if the hostent's first address is `expected`. */
+static int hostent_is(const struct hostent *host, int af,
+                      const char *expected) {
+    char text[INET6_ADDRSTRLEN];
+    if (host == NULL || host->h_addrtype != af ||
+        host->h_addr_list[0] == NULL) {
+        return 0;
+    }
+    inet_ntop(af, host->h_addr_list[0], text, sizeof(text));
+    return strcmp(text, expected) == 0;
+}
+
+/** Returns 1 if the addrinfo list's first address is `expected`. */
+static int addrinfo_is(const struct addrinfo *ai, const char *expected) {
+    char text[INET6_ADDRSTRLEN];
+    if (ai == NULL) {
+        return 0;
+    }
+    const void *address =
+        ai->ai_family == AF_INET
+            ? (const void *)&((const struct sockaddr_in *)ai->ai_addr)->sin_addr
+            : (const void *)&((const struct sockaddr_in6 *)ai->ai_addr)->sin6_addr;
+    inet_ntop(ai->ai_family, address, text, sizeof(text));
+    return strcmp(text, expected) == 0;
+}
+
+static void test_getaddrinfo(void) {
+    struct addrinfo hints;
+    memset(&hints, 0, sizeof(hints));
+    hints.ai_socktype = SOCK_STREAM;
+
+    struct addrinfo *res = NULL;
+    int rc = getaddrinfo("fail.test", "443", &hints, &res);
+    CHECK(rc == EAI_FAIL, "getaddrinfo: deny returns EAI_FAIL (got %d)", rc);
+
+    res = NULL;
+    rc = getaddrinfo("rewrite.test", "443", &hints, &res);
+    CHECK(rc == 0 && addrinfo_is(res, "192.0.2.7"),
+          "getaddrinfo: rewrite resolves to 192.0.2.7");
+    if (rc == 0) {
+        freeaddrinfo(res);
+    }
+
+    res = NULL;
+    rc = getaddrinfo("localhost", "443", &hints, &res);
+    CHECK(rc == 0, "getaddrinfo: unmatched hostname resolves normally");
+    if (rc == 0) {
+        freeaddrinfo(res);
+    }
+}
+
+static void test_gethostbyname(void) {
+    h_errno = 0;
+    struct hostent *host = gethostbyname("fail.test");
+    CHECK(host == NULL && h_errno == NO_RECOVERY,
+          "gethostbyname: deny sets NO_RECOVERY (got %d)", h_errno);
+
+    h_errno = 0;
+    host = gethostbyname("again.test");
+    CHECK(host == NULL && h_errno == TRY_AGAIN,
+          "gethostbyname: EAI_AGAIN deny sets TRY_AGAIN (got %d)", h_errno);
+
+    host = gethostbyname("rewrite.test");
+    CHECK(hostent_is(host, AF_INET, "192.0.2.7") &&
+              strcmp(host->h_name, "rewrite.test") == 0,
+          "gethostbyname: rewrite resolves to 192.0.2.7");
+
+    host = gethostbyname("localhost");
+    CHECK(host != NULL, "gethostbyname: unmatched hostname resolves normally");
+
+    struct timespec start;
+    clock_gettime(CLOCK_MONOTONIC, &start);
+    gethostbyname("slow.test");
+    long ms = elapsed_ms(&start);
+    CHECK(ms >= 100, "gethostbyname: delay waits 100ms (took %ldms)", ms);
+}
+
+static void test_gethostbyname2(void) {
+    h_errno = 0;
+    struct hostent *host = gethostbyname2("fail.test", AF_INET6);
+    CHECK(host == NULL && h_errno == NO_RECOVERY,
+          "gethostbyname2: deny sets NO_RECOVERY (got %d)", h_errno);
+
+    host = gethostbyname2("rewrite.test", AF_INET6);
+    CHECK(hostent_is(host, AF_INET6, "2001:db8::7"),
+          "gethostbyname2: rewrite returns the AF_INET6 address");
+}
+
+static void test_gethostbyname_r(void) {
+    struct hostent entry;
+    struct hostent *result = &entry;
+    char buf[1024];
+    int err = 0;
+
+    int rc = gethostbyname_r("fail.test", &entry, buf, sizeof(buf), &result,
+                             &err);
+    CHECK(rc == 0 && result == NULL && err == NO_RECOVERY,
+          "gethostbyname_r: deny sets NO_RECOVERY (got rc=%d err=%d)", rc,
+          err);
+
+    rc = gethostbyname_r("rewrite.test", &entry, buf, sizeof(buf), &result,
+                         &err);
+    CHECK(rc == 0 && hostent_is(result, AF_INET, "192.0.2.7"),
+          "gethostbyname_r: rewrite resolves to 192.0.2.7");
+
+    rc = gethostbyname_r("rewrite.test", &entry, buf, 8, &result, &err);
+    CHECK(rc == ERANGE && result == NULL,
+          "gethostbyname_r: small buffer returns ERANGE (got %d)", rc);
+
+    rc = gethostbyname2_r("rewrite.test", AF_INET6, &entry, buf, sizeof(buf),
+                          &result, &err);
+    CHECK(rc == 0 && hostent_is(result, AF_INET6, "2001:db8::7"),
+          "gethostbyname2_r: rewrite returns the AF_INET6 address");
+}
+
+static volatile sig_atomic_t notified = 0;
+
+static void on_notify(union sigval value) {
+    (void)value;
+    notified = 1;
+}
+
+static void test_getaddrinfo_a(void) {
+    struct gaicb denied = {.ar_name = "fail.test"};
+    struct gaicb rewritten = {.ar_name = "rewrite.test"};
+    struct gaicb *list[] = {&denied, &rewritten};
+
+    int rc = getaddrinfo_a(GAI_WAIT, list, 2, NULL);
+    CHECK(rc == 0, "getaddrinfo_a: GAI_WAIT returns 0 (got %d)", rc);
+    CHECK(gai_error(&denied) == EAI_FAIL,
+          "getaddrinfo_a: deny completes with EAI_FAIL (got %d)",
+          gai_error(&denied));
+    CHECK(gai_error(&rewritten) == 0 &&
+              addrinfo_is(rewritten.ar_result, "192.0.2.7"),
+          "getaddrinfo_a: rewrite resolves to 192.0.2.7");
+    if (rewritten.ar_result) {
+        freeaddrinfo(rewritten.ar_result);
+    }
+
+    struct gaicb again = {.ar_name = "again.test"};
+    struct gaicb *nowait_list[] = {&again};
+    struct sigevent sev;
+    memset(&sev, 0, sizeof(sev));
+    sev.sigev_notify = SIGEV_THREAD;
+    sev.sigev_notify_function = on_notify;
+
+    rc = getaddrinfo_a(GAI_NOWAIT, nowait_list, 1, &sev);
+    CHECK(rc == 0 && gai_error(&again) == EAI_AGAIN,
+          "getaddrinfo_a: GAI_NOWAIT deny completes with EAI_AGAIN");
+    for (int i = 0; i < 100 && !notified; i++) {
+        usleep(10000);
+    }
+    CHECK(notified, "getaddrinfo_a: GAI_NOWAIT notification delivered");
+
+    /* A delayed GAI_NOWAIT batch must not block the caller */
+    notified = 0;
+    struct gaicb slow = {.ar_name = "slow.test"};
+    struct gaicb *slow_list[] = {&slow};
+    struct timespec start;
+    clock_gettime(CLOCK_MONOTONIC, &start);
+    rc = getaddrinfo_a(GAI_NOWAIT, slow_list, 1, &sev);
+    long ms = elapsed_ms(&start);
+    CHECK(rc == 0 && ms < 50 && gai_error(&slow) == EAI_INPROGRESS,
+          "getaddrinfo_a: delayed GAI_NOWAIT returns at once (took %ldms)",
+          ms);
+    for (int i = 0; i < 100 && !notified; i++) {
+        usleep(10000);
+    }
+    ms = elapsed_ms(&start);
+    CHECK(notified && ms >= 100 && gai_error(&slow) == EAI_FAIL,
+          "getaddrinfo_a: delayed GAI_NOWAIT completes after 100ms "
+          "(took %ldms, got %d)",
+          ms, gai_error(&slow));
+}
+
+/** A deny with percentage=50 falls through to the next rule when it misses. */
+static void test_percentage(void) {
+    int failed = 0;
+    int again = 0;
+    for (int i = 0; i < 200; i++) {
+        h_errno = 0;
+        gethostbyname("coin.test");
+        if (h_errno == NO_RECOVERY) {
+            failed++;
+        } else if (h_errno == TRY_AGAIN) {
+            again++;
+        }
+    }
+    CHECK(failed > 0 && again > 0 && failed + again == 200,
+          "percentage: rolled per lookup (%d applied, %d fell through)",
+          failed, again);
+}
+
+/** Allow rules win over a later catch-all deny, as the proxy writes them. */
+static void test_allow_rules(void) {
+    write_file(DENYLIST_PATH ".tmp",
+               "allow\t^localhost$\n"
+               "deny\tresult=EAI_FAIL\t.\n");
+    rename(DENYLIST_PATH ".tmp", DENYLIST_PATH);
+
+    struct hostent *host = gethostbyname("localhost");
+    CHECK(host != NULL, "allow: allowed hostname resolves");
+
+    h_errno = 0;
+    host = gethostbyname("other.test");
+    CHECK(host == NULL && h_errno == NO_RECOVERY,
+          "allow: other hostnames hit the catch-all deny (got %d)", h_errno);
+
+    unlink(DENYLIST_PATH);
+}
+
+/** The proxy replaces the file between invocations; cached rules must follow. */
+static void test_rules_reload(void) {
+    write_file(DENYLIST_PATH ".tmp",
+               "rewrite\taddress=192.0.2.8\t^fail\\.test$\n");
+    rename(DENYLIST_PATH ".tmp", DENYLIST_PATH);
+
+    struct hostent *host = gethostbyname("fail.test");
+    CHECK(hostent_is(host, AF_INET, "192.0.2.8"),
+          "reload: replaced rules file takes effect");
+
+    unlink(DENYLIST_PATH);
+    host = gethostbyname("rewrite.test");
+    CHECK(!hostent_is(host, AF_INET, "192.0.2.7"),
+          "reload: removed rules file deactivates rules");
+}
+
+/** Listen on an ephemeral loopback port, returning the socket. */
+static int listen_loopback(unsigned short *port) {
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+    socklen_t len = sizeof(addr);
+
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    if (fd < 0 || bind(fd, (struct sockaddr *)&addr, len) != 0 ||
+        listen(fd, 4) != 0 ||
+        getsockname(fd, (struct sockaddr *)&addr, &len) != 0) {
+        perror("listen");
+        exit(2);
+    }
+    *port = ntohs(addr.sin_port);
+    return fd;
+}
+
+/**
+ * Loopback is never intercepted, but Linux routes a connection to 0.0.0.0 to
+ * the local host, so rules on 0.0.0.0 can be tested against local listeners
+ * without network access.
+ */
+static void test_connect_timeout_and_delay(void) {
+    unsigned short timeout_port;
+    unsigned short delay_port;
+    int timeout_listener = listen_loopback(&timeout_port);
+    int delay_listener = listen_loopback(&delay_port);
+
+    char rules[128];
+    snprintf(rules, sizeof(rules),
+             "timeout\tports=%u\t0.0.0.0\n"
+             "delay\tmin=100\tmax=100\tports=%u\t0.0.0.0/32\n",
+             timeout_port, delay_port);
+    write_file(CONNECT_PATH, rules);
+
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_addr.s_addr = htonl(INADDR_ANY);
+
+    /* Redirected to TEST-NET, so the local listener never sees it */
+    addr.sin_port = htons(timeout_port);
+    int fd = socket(AF_INET, SOCK_STREAM | SOCK_NONBLOCK, 0);
+    connect(fd, (struct sockaddr *)&addr, sizeof(addr));
+    struct pollfd pfd = {.fd = timeout_listener, .events = POLLIN};
+    CHECK(poll(&pfd, 1, 200) == 0,
+          "connect: timeout redirects away from the destination");
+    close(fd);
+
+    addr.sin_port = htons(delay_port);
+    fd = socket(AF_INET, SOCK_STREAM, 0);
+    struct timespec start;
+    clock_gettime(CLOCK_MONOTONIC, &start);
+    int rc = connect(fd, (struct sockaddr *)&addr, sizeof(addr));
+    long ms = elapsed_ms(&start);
+    CHECK(rc == 0 && ms >= 100,
+          "connect: delay waits 100ms, then connects (took %ldms, got %s)",
+          ms, rc == 0 ? "connected" : strerror(errno));
+    close(fd);
+
+    close(timeout_listener);
+    close(delay_listener);
+}
+
+static void test_connect(void) {
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_port = htons(443);
+    inet_pton(AF_INET, "198.51.100.1", &addr.sin_addr);
+
+    int fd = socket(AF_INET, SOCK_STREAM | SOCK_NONBLOCK, 0);
+    errno = 0;
+    int rc = connect(fd, (struct sockaddr *)&addr, sizeof(addr));
+    CHECK(rc == -1 && errno == ECONNREFUSED,
+          "connect: matching destination is refused (got %s)",
+          strerror(errno));
+    close(fd);
+
+    addr.sin_port = htons(80);
+    fd = socket(AF_INET, SOCK_STREAM | SOCK_NONBLOCK, 0);
+    errno = 0;
+    rc = connect(fd, (struct sockaddr *)&addr, sizeof(addr));
+    /* Non-blocking, so a real connection attempt doesn't wait on the network */
+    CHECK(!(rc == -1 && (errno == ECONNREFUSED || errno == ECONNRESET)),
+          "connect: other ports are not intercepted (got %s)",
+          rc == 0 ? "connected" : strerror(errno));
+    close(fd);
+
+    inet_pton(AF_INET, "198.51.100.2", &addr.sin_addr);
+    fd = socket(AF_INET, SOCK_STREAM | SOCK_NONBLOCK, 0);
+    errno = 0;
+    rc = connect(fd, (struct sockaddr *)&addr, sizeof(addr));
+    CHECK(rc == -1 && errno == ECONNRESET,
+          "connect: reset fails with ECONNRESET (got %s)", strerror(errno));
+    close(fd);
+
+    /* Connecting a UDP socket sends nothing, so this never needs the network */
+    fd = socket(AF_INET, SOCK_DGRAM, 0);
+    errno = 0;
+    rc = connect(fd, (struct sockaddr *)&addr, sizeof(addr));
+    CHECK(!(rc == -1 && errno == ECONNRESET),
+          "connect: UDP sockets are not intercepted (got %s)",
+          rc == 0 ? "connected" : strerror(errno));
+    close(fd);
+}
+
+int main(void) {
+    write_file(DENYLIST_PATH,
+               "deny\tresult=EAI_FAIL\t^fail\\.test$\n"
+               "deny\tresult=EAI_AGAIN\t^again\\.test$\n"
+               "rewrite\taddress=192.0.2.7\taddress=2001:db8::7\t"
+               "^rewrite\\.test$\n"
+               "delay\tmin=100\tmax=100\t^slow\\.test$\n"
+               "deny\tresult=EAI_FAIL\t^slow\\.test$\n"
+               "deny\tresult=EAI_FAIL\tpercentage=50\t^coin\\.test$\n"
+               "deny\tresult=EAI_AGAIN\t^coin\\.test$\n");
+    write_file(CONNECT_PATH,
+               "refuse\tports=443\t198.51.100.1\n"
+               "reset\t198.51.100.2/32\n");
+
+    test_getaddrinfo();
+    test_gethostbyname();
+    test_gethostbyname2();
+    test_gethostbyname_r();
+    test_getaddrinfo_a();
+    test_percentage();
+    test_rules_reload();
+    test_allow_rules();
+    test_connect();
+    test_connect_timeout_and_delay();
+
+    unlink(DENYLIST_PATH);
+    unlink(CONNECT_PATH);
+
+    if (failures > 0) {
+        printf("%d check(s) failed\n", failures);
+        return 1;
+    }
+    printf("all checks passed\n");
+    return 0;
+}
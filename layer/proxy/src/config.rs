@@ -12,28 +12,30 @@ pub const FAILURE_MODE_ORDER: &[&str] = &[
     "latency",
     "timeout",
     "diskspace",
+    "cpu",
+    "memory",
     "denylist",
+    "dns_latency",
+    "connection",
+    "outbound",
+    "event_mutation",
     "statuscode",
     "exception",
     "corruption",
+    "batchfailure",
 ];
 
 /// Match operators for event-based targeting.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "camelCase")]
 pub enum MatchOperator {
+    #[default]
     Eq,
     Exists,
     StartsWith,
     Regex,
 }
 
-impl Default for MatchOperator {
-    fn default() -> Self {
-        MatchOperator::Eq
-    }
-}
-
 /// Condition for event-based targeting.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MatchCondition {
@@ -44,6 +46,338 @@ pub struct MatchCondition {
     pub operator: Option<MatchOperator>,
 }
 
+/// When a failure is applied relative to the handler.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Before the event is handed to the runtime.
+    #[default]
+    Pre,
+    /// After the runtime posts its response, before it is forwarded upstream.
+    Post,
+    /// Divided between the two (latency only).
+    Split,
+}
+
+/// How `corruption` alters the handler's response.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CorruptionStrategy {
+    /// Replace the body with `body`.
+    Replace,
+    /// Truncate the body and append replacement characters.
+    Mangle,
+    /// Pad the response past `response_size_bytes`.
+    Oversize,
+    /// Flip a field's JSON type (e.g. number to string).
+    TypeFlip,
+    /// Set a field to null.
+    NullField,
+    /// Remove a field.
+    DropKey,
+    /// Shuffle an array.
+    ReorderArray,
+    /// Insert an unexpected extra field into an object.
+    ExtraField,
+    /// Flip random bits in a string.
+    BitFlip,
+    /// Truncate the body (or a string) so it is no longer valid JSON.
+    TruncateJson,
+}
+
+/// How `timeout` runs the invocation into its deadline.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutMode {
+    /// Hold the event until `timeout_buffer_ms` before the deadline.
+    #[default]
+    Deadline,
+    /// Hold the event for `timeout_percentage` of the remaining time.
+    Fraction,
+    /// Never forward the event; Lambda times out with no handler code run.
+    Hang,
+    /// Forward the event, then hold the handler's response past the deadline.
+    Response,
+}
+
+/// `getaddrinfo` result returned for a denied hostname.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub enum DnsResult {
+    /// Name does not resolve (NXDOMAIN).
+    #[default]
+    #[serde(rename = "EAI_NONAME")]
+    Noname,
+    /// Temporary failure, retried by most SDKs.
+    #[serde(rename = "EAI_AGAIN")]
+    Again,
+    /// Non-recoverable failure.
+    #[serde(rename = "EAI_FAIL")]
+    Fail,
+    /// System error, reported through `errno`.
+    #[serde(rename = "EAI_SYSTEM")]
+    System,
+    /// Resolve to unroutable TEST-NET addresses, so connections hang.
+    #[serde(rename = "BLACKHOLE")]
+    Blackhole,
+}
+
+/// Address family of the records returned by a rewritten lookup.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+/// A `deny_list` entry: a bare pattern, or a pattern with the result to
+/// return for matching hostnames.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum DenyEntry {
+    Pattern(String),
+    Rule(DenyRule),
+}
+
+impl DenyEntry {
+    /// The entry as a rule, with defaults for a bare pattern.
+    pub fn rule(&self) -> DenyRule {
+        match self {
+            DenyEntry::Pattern(pattern) => DenyRule {
+                pattern: pattern.clone(),
+                ..Default::default()
+            },
+            DenyEntry::Rule(rule) => rule.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct DenyRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub result: Option<DnsResult>,
+    /// `errno` set with `EAI_SYSTEM`.
+    #[serde(default)]
+    pub errno: Option<i32>,
+    /// Resolve matching hostnames to these addresses instead of failing.
+    #[serde(default)]
+    pub addresses: Option<Vec<String>>,
+    /// Only return records of this family from `addresses` or `BLACKHOLE`.
+    #[serde(default)]
+    pub family: Option<AddressFamily>,
+    /// Chance (0–100) that the entry applies to each individual lookup.
+    #[serde(default)]
+    pub percentage: Option<u32>,
+}
+
+impl DenyRule {
+    /// Whether matching lookups resolve to synthetic addresses.
+    pub fn is_rewrite(&self) -> bool {
+        self.addresses.is_some() || self.result == Some(DnsResult::Blackhole)
+    }
+}
+
+/// A hostname pattern whose DNS lookups `dns_latency` delays by a fixed
+/// `min_latency`, or a random delay up to `max_latency`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DnsDelay {
+    pub pattern: String,
+    pub min_latency: f64,
+    #[serde(default)]
+    pub max_latency: Option<f64>,
+}
+
+/// What the preload library does with a matching outbound `connect()`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectAction {
+    /// Fail immediately with ECONNREFUSED.
+    #[default]
+    Refuse,
+    /// Fail immediately with ECONNRESET.
+    Reset,
+    /// Connect to an unroutable address so the attempt hangs.
+    Timeout,
+    /// Wait `min_latency`–`max_latency` ms, then connect normally.
+    Delay,
+}
+
+/// A destination range whose connections `connection` interferes with.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConnectRule {
+    /// CIDR range (`10.0.0.0/8`) or single address.
+    pub cidr: String,
+    /// Destination ports the rule applies to. If omitted, every port.
+    #[serde(default)]
+    pub ports: Option<Vec<u16>>,
+    #[serde(default)]
+    pub action: ConnectAction,
+    #[serde(default)]
+    pub min_latency: Option<f64>,
+    #[serde(default)]
+    pub max_latency: Option<f64>,
+    /// Chance (0–100) that the rule applies to each individual connection.
+    #[serde(default)]
+    pub percentage: Option<u32>,
+}
+
+/// Faults the outbound forward proxy applies to connections to matching hosts.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct OutboundRule {
+    /// Regex matched against the destination hostname.
+    pub host: String,
+    /// Delay before connecting upstream, fixed or random up to `max_latency`.
+    #[serde(default)]
+    pub min_latency: Option<f64>,
+    #[serde(default)]
+    pub max_latency: Option<f64>,
+    /// Answer 502 Bad Gateway instead of connecting, as a proxy does when
+    /// the upstream refuses the connection.
+    #[serde(default)]
+    pub refuse: Option<bool>,
+    /// Reset the client connection after this many bytes from upstream.
+    #[serde(default)]
+    pub reset_after_bytes: Option<u64>,
+    /// Throttle each direction of the connection to this rate.
+    #[serde(default)]
+    pub bandwidth_bytes_per_second: Option<u64>,
+    /// Chance (0–100) that the rule applies to each individual connection.
+    #[serde(default)]
+    pub percentage: Option<u32>,
+}
+
+/// Canned error responses for `outbound_responses`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpErrorPreset {
+    /// 503 with `Retry-After`.
+    ServiceUnavailable,
+    /// 429 with `Retry-After`.
+    TooManyRequests,
+    /// 400 with an AWS JSON `ThrottlingException` body.
+    ThrottlingException,
+}
+
+/// A synthetic response the outbound proxy returns for matching plain-HTTP
+/// requests instead of forwarding them.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct OutboundResponse {
+    /// Regexes matched against the destination hostname, the request method
+    /// (whole method) and the path with query. Omitted fields match anything.
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub preset: Option<HttpErrorPreset>,
+    /// Override the preset's status, or set it when there's no preset.
+    #[serde(default)]
+    pub status_code: Option<u16>,
+    /// Added to (or replacing) the preset's headers.
+    #[serde(default)]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Chance (0–100) that the response applies to each matching request.
+    #[serde(default)]
+    pub percentage: Option<u32>,
+}
+
+/// Probability distribution `latency` samples its delay from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyDistribution {
+    #[default]
+    Uniform,
+    Normal,
+    Exponential,
+    LogNormal,
+    Pareto,
+    Empirical,
+}
+
+/// A point of an empirical latency histogram: `latency` ms at `percentile`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LatencyPercentile {
+    pub percentile: f64,
+    pub latency: f64,
+}
+
+/// Where `oversize` corruption adds its padding.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizeTarget {
+    Body,
+    Headers,
+}
+
+/// Response shape produced by `statuscode`, by invoking service.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseFormat {
+    ApiGatewayV1,
+    ApiGatewayV2,
+    FunctionUrl,
+    Alb,
+    #[serde(rename = "cloudfront")]
+    CloudFront,
+    #[serde(rename = "appsync")]
+    AppSync,
+}
+
+/// Runtime whose error payload shape an injected `exception` mimics.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorRuntime {
+    Node,
+    Python,
+    Java,
+}
+
+/// Operations supported by `event_mutation`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MutationOperation {
+    Delete,
+    Set,
+    ChangeType,
+    Truncate,
+    DropElements,
+}
+
+/// Target JSON type for the `change_type` mutation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonType {
+    String,
+    Number,
+    Boolean,
+    Array,
+    Object,
+    Null,
+}
+
+/// A single mutation applied to the incoming event by `event_mutation`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventMutation {
+    pub path: String,
+    pub operation: MutationOperation,
+    /// Value for `set`. Omitted or `null` sets the field to null.
+    #[serde(default)]
+    pub value: Option<serde_json::Value>,
+    /// Target type for `change_type`.
+    #[serde(default)]
+    pub to: Option<JsonType>,
+    /// Length kept by `truncate` (characters or array elements).
+    #[serde(default)]
+    pub length: Option<usize>,
+    /// Number of elements removed by `drop_elements`.
+    #[serde(default)]
+    pub count: Option<usize>,
+}
+
 /// A single feature flag's value.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct FlagValue {
@@ -52,12 +386,62 @@ pub struct FlagValue {
     pub percentage: Option<u32>,
     pub min_latency: Option<f64>,
     pub max_latency: Option<f64>,
+    pub distribution: Option<LatencyDistribution>,
+    /// Mean for the `normal`, `exponential` and `log_normal` distributions.
+    pub mean_latency: Option<f64>,
+    /// Standard deviation for the `normal` and `log_normal` distributions.
+    pub stddev_latency: Option<f64>,
+    pub pareto_shape: Option<f64>,
+    /// Percentile points of the `empirical` distribution.
+    pub percentiles: Option<Vec<LatencyPercentile>>,
+    pub phase: Option<Phase>,
+    /// Also hold `/error` posts in the post phase (latency only).
+    pub delay_errors: Option<bool>,
     pub exception_msg: Option<String>,
+    pub error_type: Option<String>,
+    pub stack_trace: Option<Vec<String>>,
+    /// Preset `errorType`, stack trace and payload shape for `exception`.
+    pub runtime: Option<ErrorRuntime>,
     pub status_code: Option<u16>,
+    pub headers: Option<HashMap<String, String>>,
+    pub content_type: Option<String>,
+    /// Overrides the response shape detected from the event.
+    pub response_format: Option<ResponseFormat>,
     pub disk_space: Option<u32>,
-    pub deny_list: Option<Vec<String>>,
+    /// Busy threads spun by `cpu` for the duration of the invocation.
+    pub cpu_threads: Option<u32>,
+    /// Share of each scheduling period the `cpu` threads spend busy (1–100).
+    pub cpu_duty_cycle: Option<u32>,
+    pub memory_mb: Option<u32>,
+    /// Percentage of `AWS_LAMBDA_FUNCTION_MEMORY_SIZE` allocated by `memory`.
+    pub memory_percentage: Option<u32>,
+    /// Keep each invocation's allocation, growing it until `memory_leak_max_mb`.
+    pub memory_leak: Option<bool>,
+    pub memory_leak_max_mb: Option<u32>,
+    pub deny_list: Option<Vec<DenyEntry>>,
+    /// Deny every hostname except these patterns (plus localhost, IP literals
+    /// and the Runtime API host).
+    pub allow_list: Option<Vec<String>>,
+    pub dns_delays: Option<Vec<DnsDelay>>,
+    pub connect_rules: Option<Vec<ConnectRule>>,
+    pub outbound_rules: Option<Vec<OutboundRule>>,
+    pub outbound_responses: Option<Vec<OutboundResponse>>,
     pub timeout_buffer_ms: Option<f64>,
+    pub timeout_mode: Option<TimeoutMode>,
+    /// Share of the remaining time consumed by the `fraction` timeout mode.
+    pub timeout_percentage: Option<f64>,
     pub body: Option<String>,
+    pub strategy: Option<CorruptionStrategy>,
+    pub response_size_bytes: Option<u64>,
+    pub oversize_target: Option<OversizeTarget>,
+    /// JSON paths targeted by field-level corruption strategies.
+    pub paths: Option<Vec<String>>,
+    /// Break the base64/compression layer of an encoded body instead of
+    /// corrupting the decoded payload.
+    pub break_encoding: Option<bool>,
+    pub batch_failure_percentage: Option<u32>,
+    pub batch_failure_count: Option<u32>,
+    pub mutations: Option<Vec<EventMutation>>,
     #[serde(rename = "match")]
     pub match_conditions: Option<Vec<MatchCondition>>,
 }
@@ -73,8 +457,26 @@ pub struct ResolvedFailure {
     pub flag: FlagValue,
 }
 
+impl ResolvedFailure {
+    /// Whether this failure is applied to the runtime's response rather than
+    /// before the handler runs. Latency is excluded: its post-handler share is
+    /// decided up front so a split delay is sampled once.
+    pub fn is_post_handler(&self) -> bool {
+        match self.mode.as_str() {
+            "corruption" | "batchfailure" => true,
+            "statuscode" | "exception" => self.flag.phase == Some(Phase::Post),
+            "timeout" => self.flag.timeout_mode == Some(TimeoutMode::Response),
+            _ => false,
+        }
+    }
+}
+
 const DEFAULT_CACHE_TTL_SECONDS: u64 = 60;
 
+/// Upper bound for oversize corruption, well past every Lambda payload limit
+/// but small enough not to exhaust the proxy's memory.
+const MAX_RESPONSE_SIZE_BYTES: u64 = 64 * 1024 * 1024;
+
 struct CachedConfig {
     config: FailureFlagsConfig,
     fetched_at: Instant,
@@ -382,11 +784,151 @@ pub fn parse_flags(raw: &serde_json::Value) -> FailureFlagsConfig {
     config
 }
 
+/// The DNS intercept library reads rule lines of at most 511 bytes. The
+/// longest options (a rewrite to four IPv6 addresses with a percentage) take
+/// under 240, so patterns are capped to keep every line whole.
+const MAX_DNS_PATTERN_BYTES: usize = 256;
+/// Addresses the DNS intercept library keeps per rewrite rule.
+const MAX_REWRITE_ADDRESSES: usize = 4;
+
+/// Check a hostname pattern written to the DNS intercept rules file, where
+/// each rule is one tab-separated line.
+fn validate_dns_pattern(pattern: &str) -> Option<String> {
+    if pattern.contains(['\t', '\n', '\r']) {
+        return Some("must not contain tabs or line breaks".to_string());
+    }
+    if pattern.len() > MAX_DNS_PATTERN_BYTES {
+        return Some(format!("must be at most {MAX_DNS_PATTERN_BYTES} bytes"));
+    }
+    if regex::Regex::new(pattern).is_err() {
+        return Some("invalid regular expression".to_string());
+    }
+    None
+}
+
+/// Parse a CIDR range or bare address into its network address and prefix
+/// length.
+fn parse_cidr(cidr: &str) -> Option<(std::net::IpAddr, u8)> {
+    let (address, prefix) = match cidr.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (cidr, None),
+    };
+    let ip: std::net::IpAddr = address.parse().ok()?;
+    let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse::<u8>().ok().filter(|p| *p <= max_prefix)?,
+        None => max_prefix,
+    };
+    Some((ip, prefix))
+}
+
+/// Check a rewriting deny entry: addresses must be IP literals, and at least
+/// one must be left after the family filter.
+fn validate_rewrite(rule: &DenyRule) -> Option<String> {
+    if rule.addresses.is_some() && rule.result.as_ref().is_some_and(|r| *r != DnsResult::Blackhole) {
+        return Some("addresses can't be combined with an error result".to_string());
+    }
+    let addresses = rule.addresses.as_ref()?;
+    if addresses.len() > MAX_REWRITE_ADDRESSES {
+        return Some(format!("at most {MAX_REWRITE_ADDRESSES} addresses are supported"));
+    }
+    let mut parsed = Vec::new();
+    for address in addresses {
+        match address.parse::<std::net::IpAddr>() {
+            Ok(ip) => parsed.push(ip),
+            Err(_) => return Some(format!("invalid IP address: {address}")),
+        }
+    }
+    let remaining = parsed.iter().filter(|ip| match rule.family {
+        Some(AddressFamily::Ipv4) => ip.is_ipv4(),
+        Some(AddressFamily::Ipv6) => ip.is_ipv6(),
+        None => true,
+    });
+    if remaining.count() == 0 {
+        return Some("no addresses left for the requested family".to_string());
+    }
+    None
+}
+
+/// Lambda allocates at most 6 vCPUs; more threads only add scheduling noise.
+const MAX_CPU_THREADS: u32 = 16;
+
 struct ValidationError {
     field: String,
     message: String,
 }
 
+/// Validate the parameters required by the latency `distribution`.
+fn validate_latency_distribution(mode: &str, flag: &FlagValue) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    let distribution = flag.distribution.clone().unwrap_or_default();
+    let name = serde_json::to_value(&distribution)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default();
+
+    let mut require = |field: &str, value: Option<f64>, positive: bool| match value {
+        None => errors.push(ValidationError {
+            field: format!("{mode}.{field}"),
+            message: format!("is required for the {name} distribution"),
+        }),
+        Some(v) if v < 0.0 || (positive && v == 0.0) => errors.push(ValidationError {
+            field: format!("{mode}.{field}"),
+            message: if positive { "must be a positive number" } else { "must be a non-negative number" }
+                .to_string(),
+        }),
+        Some(_) => {}
+    };
+
+    match distribution {
+        LatencyDistribution::Uniform => {}
+        LatencyDistribution::Normal => {
+            require("mean_latency", flag.mean_latency, false);
+            require("stddev_latency", flag.stddev_latency, false);
+        }
+        LatencyDistribution::Exponential => {
+            require("mean_latency", flag.mean_latency, true);
+        }
+        LatencyDistribution::LogNormal => {
+            require("mean_latency", flag.mean_latency, true);
+            require("stddev_latency", flag.stddev_latency, false);
+        }
+        LatencyDistribution::Pareto => {
+            if flag.pareto_shape.is_some() {
+                require("pareto_shape", flag.pareto_shape, true);
+            }
+        }
+        LatencyDistribution::Empirical => match flag.percentiles.as_deref() {
+            None | Some([]) => errors.push(ValidationError {
+                field: format!("{mode}.percentiles"),
+                message: "is required for the empirical distribution".to_string(),
+            }),
+            Some(points) => {
+                for (i, point) in points.iter().enumerate() {
+                    if !(0.0..=100.0).contains(&point.percentile) || point.latency < 0.0 {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.percentiles[{i}]"),
+                            message: "percentile must be between 0 and 100 and latency non-negative"
+                                .to_string(),
+                        });
+                    }
+                }
+                // Sampling interpolates between points in percentile order, which
+                // only makes sense if latency never drops as the percentile rises
+                let mut sorted: Vec<&LatencyPercentile> = points.iter().collect();
+                sorted.sort_by(|a, b| a.percentile.total_cmp(&b.percentile));
+                if sorted.windows(2).any(|pair| pair[1].latency < pair[0].latency) {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.percentiles"),
+                        message: "latency must not decrease as the percentile rises".to_string(),
+                    });
+                }
+            }
+        },
+    }
+    errors
+}
+
 fn validate_flag_value(
     mode: &str,
     flag: &FlagValue,
@@ -415,6 +957,23 @@ fn validate_flag_value(
         }
     }
 
+    match (mode, &flag.phase) {
+        (_, None) | ("latency", Some(_)) => {}
+        ("statuscode" | "exception", Some(Phase::Pre | Phase::Post)) => {}
+        ("statuscode" | "exception", Some(Phase::Split)) => {
+            errors.push(ValidationError {
+                field: format!("{mode}.phase"),
+                message: "must be one of: pre, post".to_string(),
+            });
+        }
+        (_, Some(_)) => {
+            errors.push(ValidationError {
+                field: format!("{mode}.phase"),
+                message: "is only supported for latency, statuscode and exception".to_string(),
+            });
+        }
+    }
+
     match mode {
         "latency" => {
             if let Some(min) = flag.min_latency {
@@ -441,9 +1000,10 @@ fn validate_flag_value(
                     });
                 }
             }
+            errors.extend(validate_latency_distribution(mode, flag));
         }
         "exception" => {
-            if let Some(ref raw_msg) = raw.get("exception_msg") {
+            if let Some(raw_msg) = raw.get("exception_msg") {
                 if !raw_msg.is_string() && !raw_msg.is_null() {
                     errors.push(ValidationError {
                         field: format!("{mode}.exception_msg"),
@@ -451,8 +1011,30 @@ fn validate_flag_value(
                     });
                 }
             }
+            if let Some(error_type) = flag.error_type.as_deref() {
+                if error_type.is_empty() {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.error_type"),
+                        message: "must be a non-empty string".to_string(),
+                    });
+                } else if !error_type.bytes().all(|b| b.is_ascii_graphic()) {
+                    // Sent as the lambda-runtime-function-error-type header
+                    errors.push(ValidationError {
+                        field: format!("{mode}.error_type"),
+                        message: "must contain only visible ASCII characters".to_string(),
+                    });
+                }
+            }
         }
         "statuscode" => {
+            if let Some(raw_body) = raw.get("body") {
+                if !raw_body.is_string() && !raw_body.is_null() {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.body"),
+                        message: "must be a string".to_string(),
+                    });
+                }
+            }
             if let Some(code) = flag.status_code {
                 if !(100..=599).contains(&code) {
                     errors.push(ValidationError {
@@ -473,42 +1055,362 @@ fn validate_flag_value(
             }
         }
         "denylist" => {
-            if let Some(ref patterns) = flag.deny_list {
+            if let Some(ref patterns) = flag.allow_list {
                 for (i, pattern) in patterns.iter().enumerate() {
-                    if regex::Regex::new(pattern).is_err() {
+                    if let Some(message) = validate_dns_pattern(pattern) {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.allow_list[{i}]"),
+                            message,
+                        });
+                    }
+                }
+            }
+            if let Some(ref entries) = flag.deny_list {
+                for (i, entry) in entries.iter().enumerate() {
+                    let rule = entry.rule();
+                    if let Some(message) = validate_dns_pattern(&rule.pattern) {
                         errors.push(ValidationError {
                             field: format!("{mode}.deny_list[{i}]"),
-                            message: "invalid regular expression".to_string(),
+                            message,
+                        });
+                    }
+                    if rule.percentage.is_some_and(|pct| pct > 100) {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.deny_list[{i}].percentage"),
+                            message: "must be an integer between 0 and 100".to_string(),
+                        });
+                    }
+                    if rule.is_rewrite() {
+                        if let Some(message) = validate_rewrite(&rule) {
+                            errors.push(ValidationError {
+                                field: format!("{mode}.deny_list[{i}]"),
+                                message,
+                            });
+                        }
+                    } else if rule.family.is_some() {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.deny_list[{i}].family"),
+                            message: "is only supported with addresses or BLACKHOLE".to_string(),
                         });
                     }
+                    match (&rule.result, rule.errno) {
+                        (_, Some(errno)) if errno <= 0 => errors.push(ValidationError {
+                            field: format!("{mode}.deny_list[{i}].errno"),
+                            message: "must be a positive integer".to_string(),
+                        }),
+                        (Some(DnsResult::System), _) | (_, None) => {}
+                        (_, Some(_)) => errors.push(ValidationError {
+                            field: format!("{mode}.deny_list[{i}].errno"),
+                            message: "is only supported with EAI_SYSTEM".to_string(),
+                        }),
+                    }
                 }
             }
         }
-        "timeout" => {
-            if let Some(buffer) = flag.timeout_buffer_ms {
-                if buffer < 0.0 {
+        "dns_latency" => match flag.dns_delays.as_deref() {
+            None | Some([]) => errors.push(ValidationError {
+                field: format!("{mode}.dns_delays"),
+                message: "must be a non-empty array".to_string(),
+            }),
+            Some(delays) => {
+                for (i, delay) in delays.iter().enumerate() {
+                    if let Some(message) = validate_dns_pattern(&delay.pattern) {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.dns_delays[{i}].pattern"),
+                            message,
+                        });
+                    }
+                    let max = delay.max_latency.unwrap_or(delay.min_latency);
+                    if delay.min_latency < 0.0 || max < delay.min_latency {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.dns_delays[{i}]"),
+                            message: "min_latency must be non-negative and <= max_latency".to_string(),
+                        });
+                    }
+                }
+            }
+        },
+        "connection" => match flag.connect_rules.as_deref() {
+            None | Some([]) => errors.push(ValidationError {
+                field: format!("{mode}.connect_rules"),
+                message: "must be a non-empty array".to_string(),
+            }),
+            Some(rules) => {
+                for (i, rule) in rules.iter().enumerate() {
+                    if parse_cidr(&rule.cidr).is_none() {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.connect_rules[{i}].cidr"),
+                            message: "must be an IP address or CIDR range".to_string(),
+                        });
+                    }
+                    if rule.ports.as_ref().is_some_and(|ports| ports.is_empty() || ports.contains(&0)) {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.connect_rules[{i}].ports"),
+                            message: "must be a non-empty array of ports between 1 and 65535".to_string(),
+                        });
+                    }
+                    if rule.percentage.is_some_and(|pct| pct > 100) {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.connect_rules[{i}].percentage"),
+                            message: "must be an integer between 0 and 100".to_string(),
+                        });
+                    }
+                    match (&rule.action, rule.min_latency) {
+                        (ConnectAction::Delay, None) => errors.push(ValidationError {
+                            field: format!("{mode}.connect_rules[{i}].min_latency"),
+                            message: "is required for the delay action".to_string(),
+                        }),
+                        (ConnectAction::Delay, Some(min)) => {
+                            let max = rule.max_latency.unwrap_or(min);
+                            if min < 0.0 || max < min {
+                                errors.push(ValidationError {
+                                    field: format!("{mode}.connect_rules[{i}]"),
+                                    message: "min_latency must be non-negative and <= max_latency".to_string(),
+                                });
+                            }
+                        }
+                        _ => {
+                            if rule.min_latency.is_some() || rule.max_latency.is_some() {
+                                errors.push(ValidationError {
+                                    field: format!("{mode}.connect_rules[{i}]"),
+                                    message: "latency is only supported with the delay action".to_string(),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        "outbound" => {
+            let rules = flag.outbound_rules.as_deref().unwrap_or_default();
+            let responses = flag.outbound_responses.as_deref().unwrap_or_default();
+            if rules.is_empty() && responses.is_empty() {
+                errors.push(ValidationError {
+                    field: format!("{mode}.outbound_rules"),
+                    message: "outbound_rules or outbound_responses must be a non-empty array".to_string(),
+                });
+            }
+            for (i, rule) in rules.iter().enumerate() {
+                if regex::Regex::new(&rule.host).is_err() {
                     errors.push(ValidationError {
-                        field: format!("{mode}.timeout_buffer_ms"),
-                        message: "must be a non-negative number".to_string(),
+                        field: format!("{mode}.outbound_rules[{i}].host"),
+                        message: "invalid regular expression".to_string(),
+                    });
+                }
+                if let Some(min) = rule.min_latency {
+                    let max = rule.max_latency.unwrap_or(min);
+                    if min < 0.0 || max < min {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.outbound_rules[{i}]"),
+                            message: "min_latency must be non-negative and <= max_latency".to_string(),
+                        });
+                    }
+                } else if rule.max_latency.is_some() {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.outbound_rules[{i}].min_latency"),
+                        message: "is required with max_latency".to_string(),
+                    });
+                }
+                if rule.bandwidth_bytes_per_second == Some(0) {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.outbound_rules[{i}].bandwidth_bytes_per_second"),
+                        message: "must be a positive integer".to_string(),
+                    });
+                }
+                if rule.percentage.is_some_and(|pct| pct > 100) {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.outbound_rules[{i}].percentage"),
+                        message: "must be an integer between 0 and 100".to_string(),
+                    });
+                }
+            }
+            for (i, response) in responses.iter().enumerate() {
+                for (field, pattern) in [
+                    ("host", &response.host),
+                    ("method", &response.method),
+                    ("path", &response.path),
+                ] {
+                    if pattern.as_ref().is_some_and(|p| regex::Regex::new(p).is_err()) {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.outbound_responses[{i}].{field}"),
+                            message: "invalid regular expression".to_string(),
+                        });
+                    }
+                }
+                match (response.status_code, &response.preset) {
+                    (None, None) => errors.push(ValidationError {
+                        field: format!("{mode}.outbound_responses[{i}]"),
+                        message: "status_code or preset is required".to_string(),
+                    }),
+                    // A 1xx is an interim response; the client would wait for a final one
+                    (Some(code), _) if !(200..=599).contains(&code) => errors.push(ValidationError {
+                        field: format!("{mode}.outbound_responses[{i}].status_code"),
+                        message: "must be a final HTTP status code (200-599)".to_string(),
+                    }),
+                    (Some(204 | 304), _) if response.body.is_some() => errors.push(ValidationError {
+                        field: format!("{mode}.outbound_responses[{i}].body"),
+                        message: "is not allowed with status_code 204 or 304".to_string(),
+                    }),
+                    _ => {}
+                }
+                let header_invalid = response.headers.iter().flatten().any(|(name, value)| {
+                    name.is_empty() || name.contains([':', '\r', '\n']) || value.contains(['\r', '\n'])
+                });
+                if header_invalid {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.outbound_responses[{i}].headers"),
+                        message: "header names and values must not contain line breaks".to_string(),
+                    });
+                }
+                if response.percentage.is_some_and(|pct| pct > 100) {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.outbound_responses[{i}].percentage"),
+                        message: "must be an integer between 0 and 100".to_string(),
                     });
                 }
             }
         }
-        "corruption" => {
-            if let Some(ref raw_body) = raw.get("body") {
-                if !raw_body.is_string() && !raw_body.is_null() {
+        "cpu" => {
+            if let Some(threads) = flag.cpu_threads {
+                if !(1..=MAX_CPU_THREADS).contains(&threads) {
                     errors.push(ValidationError {
-                        field: format!("{mode}.body"),
-                        message: "must be a string".to_string(),
+                        field: format!("{mode}.cpu_threads"),
+                        message: format!("must be an integer between 1 and {MAX_CPU_THREADS}"),
+                    });
+                }
+            }
+            if let Some(duty_cycle) = flag.cpu_duty_cycle {
+                if !(1..=100).contains(&duty_cycle) {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.cpu_duty_cycle"),
+                        message: "must be an integer between 1 and 100".to_string(),
                     });
                 }
             }
         }
-        _ => {}
-    }
-
-    // Validate match conditions
-    if let Some(ref conditions) = flag.match_conditions {
+        "memory" => {
+            match (flag.memory_mb, flag.memory_percentage) {
+                (None, None) => {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.memory_mb"),
+                        message: "one of memory_mb or memory_percentage is required".to_string(),
+                    });
+                }
+                (Some(mb), _) if mb == 0 || mb > 10240 => {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.memory_mb"),
+                        message: "must be between 1 and 10240 (MB)".to_string(),
+                    });
+                }
+                (_, Some(pct)) if !(1..=100).contains(&pct) => {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.memory_percentage"),
+                        message: "must be an integer between 1 and 100".to_string(),
+                    });
+                }
+                _ => {}
+            }
+            if flag.memory_leak_max_mb == Some(0) {
+                errors.push(ValidationError {
+                    field: format!("{mode}.memory_leak_max_mb"),
+                    message: "must be a positive integer".to_string(),
+                });
+            }
+        }
+        "timeout" => {
+            if let Some(buffer) = flag.timeout_buffer_ms {
+                if buffer < 0.0 {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.timeout_buffer_ms"),
+                        message: "must be a non-negative number".to_string(),
+                    });
+                }
+            }
+            match (&flag.timeout_mode, flag.timeout_percentage) {
+                (Some(TimeoutMode::Fraction), None) => {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.timeout_percentage"),
+                        message: "is required for the fraction timeout mode".to_string(),
+                    });
+                }
+                (_, Some(pct)) if !(0.0..=100.0).contains(&pct) => {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.timeout_percentage"),
+                        message: "must be a number between 0 and 100".to_string(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        "corruption" => {
+            if let Some(raw_body) = raw.get("body") {
+                if !raw_body.is_string() && !raw_body.is_null() {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.body"),
+                        message: "must be a string".to_string(),
+                    });
+                }
+            }
+            if flag.strategy == Some(CorruptionStrategy::Replace) && flag.body.is_none() {
+                errors.push(ValidationError {
+                    field: format!("{mode}.body"),
+                    message: "is required for the replace strategy".to_string(),
+                });
+            }
+            if let Some(ref paths) = flag.paths {
+                for (i, path) in paths.iter().enumerate() {
+                    if path.is_empty() {
+                        errors.push(ValidationError {
+                            field: format!("{mode}.paths[{i}]"),
+                            message: "must be a non-empty string".to_string(),
+                        });
+                    }
+                }
+            }
+            if let Some(size) = flag.response_size_bytes {
+                if size == 0 || size > MAX_RESPONSE_SIZE_BYTES {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.response_size_bytes"),
+                        message: format!("must be between 1 and {MAX_RESPONSE_SIZE_BYTES}"),
+                    });
+                }
+            }
+        }
+        "event_mutation" => {
+            match flag.mutations {
+                Some(ref mutations) if !mutations.is_empty() => {
+                    for (i, mutation) in mutations.iter().enumerate() {
+                        if mutation.path.is_empty() {
+                            errors.push(ValidationError {
+                                field: format!("{mode}.mutations[{i}].path"),
+                                message: "must be a non-empty string".to_string(),
+                            });
+                        }
+                    }
+                }
+                _ => {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.mutations"),
+                        message: "must be a non-empty array".to_string(),
+                    });
+                }
+            }
+        }
+        "batchfailure" => {
+            if let Some(pct) = flag.batch_failure_percentage {
+                if pct > 100 {
+                    errors.push(ValidationError {
+                        field: format!("{mode}.batch_failure_percentage"),
+                        message: "must be an integer between 0 and 100".to_string(),
+                    });
+                }
+            }
+        }
+        _ => {}
+    }
+
+    // Validate match conditions
+    if let Some(ref conditions) = flag.match_conditions {
         let valid_operators = ["eq", "exists", "startsWith", "regex"];
         for (i, cond) in conditions.iter().enumerate() {
             if cond.path.is_empty() {
@@ -718,6 +1620,29 @@ mod tests {
         assert!(config.is_empty());
     }
 
+    #[test]
+    fn test_parse_statuscode_response_options() {
+        let json: serde_json::Value = serde_json::json!({
+            "statuscode": {
+                "enabled": true,
+                "status_code": 503,
+                "headers": { "Retry-After": "30" },
+                "content_type": "text/plain",
+                "response_format": "alb"
+            }
+        });
+        let config = parse_flags(&json);
+        let statuscode = config.get("statuscode").unwrap();
+        assert_eq!(statuscode.headers.as_ref().unwrap()["Retry-After"], "30");
+        assert_eq!(statuscode.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(statuscode.response_format, Some(ResponseFormat::Alb));
+
+        let json: serde_json::Value = serde_json::json!({
+            "statuscode": { "enabled": true, "response_format": "grpc" }
+        });
+        assert!(parse_flags(&json).is_empty());
+    }
+
     #[test]
     fn test_validate_diskspace_range() {
         let json: serde_json::Value = serde_json::json!({
@@ -767,21 +1692,600 @@ mod tests {
             "timeout": { "enabled": true, "timeout_buffer_ms": 50.0 },
             "diskspace": { "enabled": true, "disk_space": 100 },
             "denylist": { "enabled": true, "deny_list": [".*\\.example\\.com"] },
+            "event_mutation": { "enabled": true, "mutations": [{ "path": "body", "operation": "delete" }] },
             "statuscode": { "enabled": true, "status_code": 503 },
             "exception": { "enabled": true, "exception_msg": "chaos" },
-            "corruption": { "enabled": true, "body": "corrupted" }
+            "corruption": { "enabled": true, "body": "corrupted" },
+            "batchfailure": { "enabled": true, "batch_failure_count": 2 }
         });
         let config = parse_flags(&json);
-        assert_eq!(config.len(), 7);
+        assert_eq!(config.len(), 9);
 
         let failures = resolve_failures(&config);
-        assert_eq!(failures.len(), 7);
+        assert_eq!(failures.len(), 9);
         assert_eq!(failures[0].mode, "latency");
         assert_eq!(failures[1].mode, "timeout");
         assert_eq!(failures[2].mode, "diskspace");
         assert_eq!(failures[3].mode, "denylist");
-        assert_eq!(failures[4].mode, "statuscode");
-        assert_eq!(failures[5].mode, "exception");
-        assert_eq!(failures[6].mode, "corruption");
+        assert_eq!(failures[4].mode, "event_mutation");
+        assert_eq!(failures[5].mode, "statuscode");
+        assert_eq!(failures[6].mode, "exception");
+        assert_eq!(failures[7].mode, "corruption");
+        assert_eq!(failures[8].mode, "batchfailure");
+    }
+
+    #[test]
+    fn test_validate_batch_failure_percentage_range() {
+        let json: serde_json::Value = serde_json::json!({
+            "batchfailure": { "enabled": true, "batch_failure_percentage": 150 }
+        });
+        let config = parse_flags(&json);
+        assert!(config.is_empty());
+    }
+
+    #[test]
+    fn test_parse_latency_phase() {
+        let json: serde_json::Value = serde_json::json!({
+            "latency": { "enabled": true, "phase": "split", "delay_errors": true }
+        });
+        let config = parse_flags(&json);
+        let latency = config.get("latency").unwrap();
+        assert_eq!(latency.phase, Some(Phase::Split));
+        assert_eq!(latency.delay_errors, Some(true));
+    }
+
+    #[test]
+    fn test_validate_phase_unsupported_mode() {
+        let json: serde_json::Value = serde_json::json!({
+            "diskspace": { "enabled": true, "phase": "post" }
+        });
+        assert!(parse_flags(&json).is_empty());
+
+        let json: serde_json::Value = serde_json::json!({
+            "statuscode": { "enabled": true, "phase": "split" }
+        });
+        assert!(parse_flags(&json).is_empty());
+
+        let json: serde_json::Value = serde_json::json!({
+            "latency": { "enabled": true, "phase": "sometimes" }
+        });
+        assert!(parse_flags(&json).is_empty());
+    }
+
+    #[test]
+    fn test_parse_event_mutations() {
+        let json: serde_json::Value = serde_json::json!({
+            "event_mutation": {
+                "enabled": true,
+                "mutations": [
+                    { "path": "headers.authorization", "operation": "delete" },
+                    { "path": "body", "operation": "set", "value": null },
+                    { "path": "queryStringParameters.limit", "operation": "change_type", "to": "number" },
+                    { "path": "Records", "operation": "drop_elements", "count": 2 }
+                ]
+            }
+        });
+        let config = parse_flags(&json);
+        let mutations = config["event_mutation"].mutations.as_ref().unwrap();
+        assert_eq!(mutations.len(), 4);
+        assert_eq!(mutations[0].operation, MutationOperation::Delete);
+        assert!(mutations[1].value.is_none());
+        assert_eq!(mutations[2].to, Some(JsonType::Number));
+        assert_eq!(mutations[3].count, Some(2));
+    }
+
+    #[test]
+    fn test_validate_event_mutation_requires_mutations() {
+        let json: serde_json::Value = serde_json::json!({
+            "event_mutation": { "enabled": true, "mutations": [] }
+        });
+        assert!(parse_flags(&json).is_empty());
+
+        let json: serde_json::Value = serde_json::json!({
+            "event_mutation": { "enabled": true, "mutations": [{ "path": "", "operation": "delete" }] }
+        });
+        assert!(parse_flags(&json).is_empty());
+    }
+
+    #[test]
+    fn test_parse_corruption_oversize() {
+        let json: serde_json::Value = serde_json::json!({
+            "corruption": {
+                "enabled": true,
+                "strategy": "oversize",
+                "response_size_bytes": 7000000,
+                "oversize_target": "headers"
+            }
+        });
+        let config = parse_flags(&json);
+        let corruption = config.get("corruption").unwrap();
+        assert_eq!(corruption.strategy, Some(CorruptionStrategy::Oversize));
+        assert_eq!(corruption.response_size_bytes, Some(7_000_000));
+        assert_eq!(corruption.oversize_target, Some(OversizeTarget::Headers));
+    }
+
+    #[test]
+    fn test_parse_corruption_field_strategies() {
+        for strategy in [
+            "type_flip",
+            "null_field",
+            "drop_key",
+            "reorder_array",
+            "extra_field",
+            "bit_flip",
+            "truncate_json",
+        ] {
+            let json: serde_json::Value = serde_json::json!({
+                "corruption": { "enabled": true, "strategy": strategy, "paths": ["body.id"] }
+            });
+            let config = parse_flags(&json);
+            assert!(config.contains_key("corruption"), "{strategy} should parse");
+        }
+    }
+
+    #[test]
+    fn test_parse_corruption_break_encoding() {
+        let json: serde_json::Value = serde_json::json!({
+            "corruption": { "enabled": true, "break_encoding": true }
+        });
+        let config = parse_flags(&json);
+        assert_eq!(config["corruption"].break_encoding, Some(true));
+    }
+
+    #[test]
+    fn test_validate_corruption_strategy() {
+        let json: serde_json::Value = serde_json::json!({
+            "corruption": { "enabled": true, "strategy": "replace" }
+        });
+        assert!(parse_flags(&json).is_empty());
+
+        let json: serde_json::Value = serde_json::json!({
+            "corruption": { "enabled": true, "strategy": "oversize", "response_size_bytes": 0 }
+        });
+        assert!(parse_flags(&json).is_empty());
+    }
+
+    #[test]
+    fn test_is_post_handler() {
+        let json: serde_json::Value = serde_json::json!({
+            "latency": { "enabled": true },
+            "corruption": { "enabled": true },
+            "batchfailure": { "enabled": true }
+        });
+        let failures = resolve_failures(&parse_flags(&json));
+        assert!(!failures[0].is_post_handler());
+        assert!(failures[1].is_post_handler());
+        assert!(failures[2].is_post_handler());
+    }
+
+    #[test]
+    fn test_statuscode_post_phase_is_post_handler() {
+        let json: serde_json::Value = serde_json::json!({
+            "statuscode": { "enabled": true, "status_code": 502, "phase": "post" }
+        });
+        let failures = resolve_failures(&parse_flags(&json));
+        assert!(failures[0].is_post_handler());
+
+        let json: serde_json::Value = serde_json::json!({
+            "statuscode": { "enabled": true, "status_code": 502 }
+        });
+        let failures = resolve_failures(&parse_flags(&json));
+        assert!(!failures[0].is_post_handler());
+    }
+
+    #[test]
+    fn test_exception_post_phase() {
+        let json: serde_json::Value = serde_json::json!({
+            "exception": {
+                "enabled": true,
+                "phase": "post",
+                "error_type": "DownstreamTimeout",
+                "exception_msg": "write succeeded, response lost"
+            }
+        });
+        let failures = resolve_failures(&parse_flags(&json));
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].is_post_handler());
+        assert_eq!(failures[0].flag.error_type.as_deref(), Some("DownstreamTimeout"));
+    }
+
+    #[test]
+    fn test_validate_exception_error_type() {
+        let json: serde_json::Value = serde_json::json!({
+            "exception": { "enabled": true, "error_type": "" }
+        });
+        assert!(parse_flags(&json).is_empty());
+
+        for error_type in ["Bad\nType", "Bad Type", "Tipo\u{e9}"] {
+            let json: serde_json::Value = serde_json::json!({
+                "exception": { "enabled": true, "error_type": error_type }
+            });
+            assert!(parse_flags(&json).is_empty(), "{error_type:?} accepted");
+        }
+    }
+
+    #[test]
+    fn test_exception_runtime_and_stack_trace() {
+        let json: serde_json::Value = serde_json::json!({
+            "exception": {
+                "enabled": true,
+                "runtime": "python",
+                "stack_trace": ["  File \"/var/task/app.py\", line 7, in handler\n"]
+            }
+        });
+        let config = parse_flags(&json);
+        let exception = config.get("exception").unwrap();
+        assert_eq!(exception.runtime, Some(ErrorRuntime::Python));
+        assert_eq!(exception.stack_trace.as_ref().unwrap().len(), 1);
+
+        let json: serde_json::Value = serde_json::json!({
+            "exception": { "enabled": true, "runtime": "ruby" }
+        });
+        assert!(parse_flags(&json).is_empty());
+    }
+
+    #[test]
+    fn test_latency_distribution() {
+        let json: serde_json::Value = serde_json::json!({
+            "latency": {
+                "enabled": true,
+                "distribution": "empirical",
+                "max_latency": 3000,
+                "percentiles": [
+                    { "percentile": 50, "latency": 120 },
+                    { "percentile": 99, "latency": 1800 }
+                ]
+            }
+        });
+        let config = parse_flags(&json);
+        let latency = config.get("latency").unwrap();
+        assert_eq!(latency.distribution, Some(LatencyDistribution::Empirical));
+        assert_eq!(latency.percentiles.as_ref().unwrap()[1].latency, 1800.0);
+    }
+
+    #[test]
+    fn test_validate_latency_distribution_parameters() {
+        for flag in [
+            serde_json::json!({ "enabled": true, "distribution": "normal", "mean_latency": 100 }),
+            serde_json::json!({ "enabled": true, "distribution": "exponential", "mean_latency": 0 }),
+            serde_json::json!({ "enabled": true, "distribution": "pareto", "pareto_shape": -1 }),
+            serde_json::json!({ "enabled": true, "distribution": "empirical", "percentiles": [] }),
+            serde_json::json!({
+                "enabled": true,
+                "distribution": "empirical",
+                "percentiles": [{ "percentile": 101, "latency": 10 }]
+            }),
+            serde_json::json!({
+                "enabled": true,
+                "distribution": "empirical",
+                "percentiles": [
+                    { "percentile": 50, "latency": 200 },
+                    { "percentile": 90, "latency": 100 }
+                ]
+            }),
+        ] {
+            let json = serde_json::json!({ "latency": flag });
+            assert!(parse_flags(&json).is_empty(), "{json}");
+        }
+
+        let json: serde_json::Value = serde_json::json!({
+            "latency": { "enabled": true, "distribution": "log_normal", "mean_latency": 100, "stddev_latency": 50 }
+        });
+        assert_eq!(parse_flags(&json).len(), 1);
+    }
+
+    #[test]
+    fn test_timeout_modes() {
+        let json: serde_json::Value = serde_json::json!({
+            "timeout": { "enabled": true, "timeout_mode": "response" }
+        });
+        let failures = resolve_failures(&parse_flags(&json));
+        assert!(failures[0].is_post_handler());
+
+        let json: serde_json::Value = serde_json::json!({
+            "timeout": { "enabled": true, "timeout_mode": "fraction", "timeout_percentage": 80 }
+        });
+        let failures = resolve_failures(&parse_flags(&json));
+        assert!(!failures[0].is_post_handler());
+        assert_eq!(failures[0].flag.timeout_percentage, Some(80.0));
+    }
+
+    #[test]
+    fn test_validate_timeout_percentage() {
+        let json: serde_json::Value = serde_json::json!({
+            "timeout": { "enabled": true, "timeout_mode": "fraction" }
+        });
+        assert!(parse_flags(&json).is_empty());
+
+        let json: serde_json::Value = serde_json::json!({
+            "timeout": { "enabled": true, "timeout_mode": "fraction", "timeout_percentage": 150 }
+        });
+        assert!(parse_flags(&json).is_empty());
+    }
+
+    #[test]
+    fn test_cpu_flag() {
+        let json: serde_json::Value = serde_json::json!({
+            "cpu": { "enabled": true, "cpu_threads": 2, "cpu_duty_cycle": 75 },
+            "latency": { "enabled": true, "min_latency": 10, "max_latency": 10 },
+            "denylist": { "enabled": true, "deny_list": ["s3.amazonaws.com"] }
+        });
+        let failures = resolve_failures(&parse_flags(&json));
+        let modes: Vec<&str> = failures.iter().map(|f| f.mode.as_str()).collect();
+        assert_eq!(modes, vec!["latency", "cpu", "denylist"]);
+        assert_eq!(failures[1].flag.cpu_threads, Some(2));
+
+        for flag in [
+            serde_json::json!({ "enabled": true, "cpu_threads": 0 }),
+            serde_json::json!({ "enabled": true, "cpu_duty_cycle": 0 }),
+            serde_json::json!({ "enabled": true, "cpu_duty_cycle": 101 }),
+        ] {
+            assert!(parse_flags(&serde_json::json!({ "cpu": flag })).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_memory_flag() {
+        let json: serde_json::Value = serde_json::json!({
+            "memory": { "enabled": true, "memory_percentage": 80, "memory_leak": true, "memory_leak_max_mb": 900 }
+        });
+        let config = parse_flags(&json);
+        let memory = config.get("memory").unwrap();
+        assert_eq!(memory.memory_percentage, Some(80));
+        assert_eq!(memory.memory_leak, Some(true));
+
+        for flag in [
+            serde_json::json!({ "enabled": true }),
+            serde_json::json!({ "enabled": true, "memory_mb": 0 }),
+            serde_json::json!({ "enabled": true, "memory_mb": 10241 }),
+            serde_json::json!({ "enabled": true, "memory_percentage": 120 }),
+            serde_json::json!({ "enabled": true, "memory_mb": 64, "memory_leak_max_mb": 0 }),
+        ] {
+            assert!(parse_flags(&serde_json::json!({ "memory": flag })).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_dns_latency_flag() {
+        let json: serde_json::Value = serde_json::json!({
+            "dns_latency": {
+                "enabled": true,
+                "dns_delays": [
+                    { "pattern": "\\.amazonaws\\.com$", "min_latency": 500, "max_latency": 2000 },
+                    { "pattern": "example\\.com", "min_latency": 100 }
+                ]
+            }
+        });
+        let failures = resolve_failures(&parse_flags(&json));
+        assert_eq!(failures[0].mode, "dns_latency");
+        let delays = failures[0].flag.dns_delays.as_ref().unwrap();
+        assert_eq!(delays[1].max_latency, None);
+
+        for flag in [
+            serde_json::json!({ "enabled": true }),
+            serde_json::json!({ "enabled": true, "dns_delays": [{ "pattern": "[bad", "min_latency": 1 }] }),
+            serde_json::json!({ "enabled": true, "dns_delays": [{ "pattern": "a\tb", "min_latency": 1 }] }),
+            serde_json::json!({ "enabled": true, "dns_delays": [{ "pattern": "a", "min_latency": 10, "max_latency": 5 }] }),
+        ] {
+            assert!(parse_flags(&serde_json::json!({ "dns_latency": flag })).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_connection_flag() {
+        let json: serde_json::Value = serde_json::json!({
+            "connection": {
+                "enabled": true,
+                "connect_rules": [
+                    { "cidr": "10.0.0.0/8", "ports": [443] },
+                    { "cidr": "2001:db8::1", "action": "reset" },
+                    { "cidr": "172.16.0.0/12", "action": "delay", "min_latency": 100, "max_latency": 500 }
+                ]
+            }
+        });
+        let failures = resolve_failures(&parse_flags(&json));
+        assert_eq!(failures[0].mode, "connection");
+        let rules = failures[0].flag.connect_rules.as_ref().unwrap();
+        assert_eq!(rules[0].action, ConnectAction::Refuse);
+        assert_eq!(rules[1].action, ConnectAction::Reset);
+
+        for rule in [
+            serde_json::json!({ "cidr": "10.0.0.0/33" }),
+            serde_json::json!({ "cidr": "example.com" }),
+            serde_json::json!({ "cidr": "10.0.0.0/8", "ports": [] }),
+            serde_json::json!({ "cidr": "10.0.0.0/8", "percentage": 101 }),
+            serde_json::json!({ "cidr": "10.0.0.0/8", "action": "delay" }),
+            serde_json::json!({ "cidr": "10.0.0.0/8", "action": "delay", "min_latency": 10, "max_latency": 5 }),
+            serde_json::json!({ "cidr": "10.0.0.0/8", "action": "refuse", "min_latency": 10 }),
+        ] {
+            let flag = serde_json::json!({ "connection": { "enabled": true, "connect_rules": [rule] } });
+            assert!(parse_flags(&flag).is_empty());
+        }
+        assert!(parse_flags(&serde_json::json!({ "connection": { "enabled": true } })).is_empty());
+    }
+
+    #[test]
+    fn test_outbound_flag() {
+        let json: serde_json::Value = serde_json::json!({
+            "outbound": {
+                "enabled": true,
+                "outbound_rules": [
+                    { "host": "dynamodb\\..*\\.amazonaws\\.com$", "min_latency": 200, "max_latency": 800 },
+                    { "host": "^s3\\.", "refuse": true, "percentage": 50 },
+                    { "host": ".", "reset_after_bytes": 1024, "bandwidth_bytes_per_second": 4096 }
+                ]
+            }
+        });
+        let failures = resolve_failures(&parse_flags(&json));
+        assert_eq!(failures[0].mode, "outbound");
+        let rules = failures[0].flag.outbound_rules.as_ref().unwrap();
+        assert_eq!(rules[1].refuse, Some(true));
+        assert_eq!(rules[2].reset_after_bytes, Some(1024));
+
+        for rule in [
+            serde_json::json!({ "host": "(unclosed" }),
+            serde_json::json!({ "host": "a", "min_latency": 10, "max_latency": 5 }),
+            serde_json::json!({ "host": "a", "max_latency": 5 }),
+            serde_json::json!({ "host": "a", "bandwidth_bytes_per_second": 0 }),
+            serde_json::json!({ "host": "a", "percentage": 101 }),
+        ] {
+            let flag = serde_json::json!({ "outbound": { "enabled": true, "outbound_rules": [rule] } });
+            assert!(parse_flags(&flag).is_empty());
+        }
+        assert!(parse_flags(&serde_json::json!({ "outbound": { "enabled": true } })).is_empty());
+    }
+
+    #[test]
+    fn test_outbound_responses() {
+        let json: serde_json::Value = serde_json::json!({
+            "outbound": {
+                "enabled": true,
+                "outbound_responses": [
+                    { "host": "^localhost$", "method": "GET|POST", "path": "^/items", "preset": "service_unavailable" },
+                    { "status_code": 418, "headers": { "x-test": "1" }, "body": "teapot", "percentage": 25 }
+                ]
+            }
+        });
+        let config = parse_flags(&json);
+        let responses = config.get("outbound").unwrap().outbound_responses.as_ref().unwrap();
+        assert_eq!(responses[0].preset, Some(HttpErrorPreset::ServiceUnavailable));
+        assert_eq!(responses[1].status_code, Some(418));
+
+        for response in [
+            serde_json::json!({ "host": "a" }),
+            serde_json::json!({ "path": "(unclosed", "status_code": 503 }),
+            serde_json::json!({ "status_code": 99 }),
+            serde_json::json!({ "status_code": 103 }),
+            serde_json::json!({ "status_code": 204, "body": "x" }),
+            serde_json::json!({ "status_code": 304, "body": "" }),
+            serde_json::json!({ "preset": "too_many_requests", "headers": { "x-bad": "a\r\nb" } }),
+            serde_json::json!({ "preset": "too_many_requests", "percentage": 101 }),
+            serde_json::json!({ "preset": "bogus" }),
+        ] {
+            let flag = serde_json::json!({ "outbound": { "enabled": true, "outbound_responses": [response] } });
+            assert!(parse_flags(&flag).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        assert_eq!(parse_cidr("10.0.0.0/8"), Some(("10.0.0.0".parse().unwrap(), 8)));
+        assert_eq!(parse_cidr("::1"), Some(("::1".parse().unwrap(), 128)));
+        assert_eq!(parse_cidr("10.0.0.1"), Some(("10.0.0.1".parse().unwrap(), 32)));
+        assert_eq!(parse_cidr("10.0.0.0/"), None);
+        assert_eq!(parse_cidr("2001:db8::/129"), None);
+    }
+
+    #[test]
+    fn test_deny_list_entries() {
+        let json: serde_json::Value = serde_json::json!({
+            "denylist": {
+                "enabled": true,
+                "deny_list": [
+                    "s3\\.amazonaws\\.com",
+                    { "pattern": "dynamodb", "result": "EAI_AGAIN" },
+                    { "pattern": "sqs", "result": "EAI_SYSTEM", "errno": 110 }
+                ]
+            }
+        });
+        let config = parse_flags(&json);
+        let entries = config.get("denylist").unwrap().deny_list.as_ref().unwrap();
+        assert_eq!(entries[0].rule().result, None);
+        assert_eq!(entries[1].rule().result, Some(DnsResult::Again));
+        assert_eq!(entries[2].rule().errno, Some(110));
+
+        for entry in [
+            serde_json::json!({ "pattern": "sqs", "result": "EAI_BOGUS" }),
+            serde_json::json!({ "pattern": "sqs", "result": "EAI_AGAIN", "errno": 110 }),
+            serde_json::json!({ "pattern": "sqs", "result": "EAI_SYSTEM", "errno": 0 }),
+            serde_json::json!({ "pattern": "[bad", "result": "EAI_FAIL" }),
+        ] {
+            let json = serde_json::json!({ "denylist": { "enabled": true, "deny_list": [entry] } });
+            assert!(parse_flags(&json).is_empty(), "{json}");
+        }
+    }
+
+    #[test]
+    fn test_deny_list_rewrite_entries() {
+        let json: serde_json::Value = serde_json::json!({
+            "denylist": {
+                "enabled": true,
+                "deny_list": [
+                    { "pattern": "s3", "result": "BLACKHOLE", "family": "ipv6" },
+                    { "pattern": "dynamodb", "addresses": ["127.0.0.1", "::1"] }
+                ]
+            }
+        });
+        let config = parse_flags(&json);
+        let entries = config.get("denylist").unwrap().deny_list.as_ref().unwrap();
+        assert!(entries[0].rule().is_rewrite());
+        assert_eq!(entries[0].rule().family, Some(AddressFamily::Ipv6));
+        assert!(entries[1].rule().is_rewrite());
+
+        for entry in [
+            serde_json::json!({ "pattern": "s3", "addresses": ["not-an-ip"] }),
+            serde_json::json!({ "pattern": "s3", "addresses": ["127.0.0.1"], "family": "ipv6" }),
+            serde_json::json!({ "pattern": "s3", "addresses": ["127.0.0.1"], "result": "EAI_AGAIN" }),
+            serde_json::json!({ "pattern": "s3", "family": "ipv4" }),
+        ] {
+            let json = serde_json::json!({ "denylist": { "enabled": true, "deny_list": [entry] } });
+            assert!(parse_flags(&json).is_empty(), "{json}");
+        }
+    }
+
+    #[test]
+    fn test_dns_rule_line_limits() {
+        let long = "a".repeat(MAX_DNS_PATTERN_BYTES + 1);
+        let addresses = ["192.0.2.1", "192.0.2.2", "192.0.2.3", "192.0.2.4", "192.0.2.5"];
+        for flag in [
+            serde_json::json!({ "denylist": { "enabled": true, "deny_list": [long] } }),
+            serde_json::json!({ "denylist": { "enabled": true, "allow_list": [long] } }),
+            serde_json::json!({ "denylist": { "enabled": true, "deny_list": [{ "pattern": "s3", "addresses": addresses }] } }),
+            serde_json::json!({ "dns_latency": { "enabled": true, "dns_delays": [{ "pattern": long, "min_latency": 1 }] } }),
+        ] {
+            assert!(parse_flags(&flag).is_empty(), "{flag}");
+        }
+
+        let json = serde_json::json!({
+            "denylist": {
+                "enabled": true,
+                "deny_list": [{ "pattern": "a".repeat(MAX_DNS_PATTERN_BYTES), "addresses": &addresses[..4] }]
+            }
+        });
+        assert_eq!(parse_flags(&json).len(), 1);
+    }
+
+    #[test]
+    fn test_deny_list_entry_percentage() {
+        let json: serde_json::Value = serde_json::json!({
+            "denylist": {
+                "enabled": true,
+                "deny_list": [{ "pattern": "s3", "result": "EAI_AGAIN", "percentage": 30 }]
+            }
+        });
+        let config = parse_flags(&json);
+        let entries = config.get("denylist").unwrap().deny_list.as_ref().unwrap();
+        assert_eq!(entries[0].rule().percentage, Some(30));
+
+        let json: serde_json::Value = serde_json::json!({
+            "denylist": { "enabled": true, "deny_list": [{ "pattern": "s3", "percentage": 101 }] }
+        });
+        assert!(parse_flags(&json).is_empty());
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let json: serde_json::Value = serde_json::json!({
+            "denylist": { "enabled": true, "allow_list": ["\\.internal$"] }
+        });
+        let config = parse_flags(&json);
+        let denylist = config.get("denylist").unwrap();
+        assert_eq!(denylist.allow_list.as_ref().unwrap().len(), 1);
+        assert!(denylist.deny_list.is_none());
+
+        let json: serde_json::Value = serde_json::json!({
+            "denylist": { "enabled": true, "allow_list": ["(unclosed"] }
+        });
+        assert!(parse_flags(&json).is_empty());
     }
 }
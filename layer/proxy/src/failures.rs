@@ -2,64 +2,223 @@ use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
 use std::path::Path;
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
+use rand::seq::SliceRandom;
 use rand::Rng;
+use rand_distr::{Distribution, Exp, LogNormal, Normal, Pareto};
 use regex::Regex;
 use tracing::{info, warn, error};
 
-use crate::config::{FlagValue, MatchCondition, MatchOperator};
+use crate::config::{
+    AddressFamily, ConnectAction, ConnectRule, CorruptionStrategy, DenyRule, DnsResult,
+    ErrorRuntime, EventMutation, FlagValue, JsonType, LatencyDistribution, LatencyPercentile,
+    MatchCondition, MatchOperator, MutationOperation, OversizeTarget, Phase, ResponseFormat,
+    TimeoutMode,
+};
 
-/// Inject latency by sleeping for a random duration in [min_latency, max_latency].
-pub async fn inject_latency(flag: &FlagValue) {
-    let min_latency = flag.min_latency.unwrap_or(0.0).max(0.0);
-    let max_latency = flag.max_latency.unwrap_or(0.0).max(0.0);
-    let range = (max_latency - min_latency).max(0.0);
-    let injected_latency = min_latency + rand::thread_rng().gen::<f64>() * range;
+/// Inject latency by sampling a duration from `distribution`, clamped to
+/// [min_latency, max_latency], and dividing it according to `phase`. Sleeps for
+/// the pre-handler share and returns the post-handler share in ms, for the
+/// caller to hold the runtime's response.
+pub async fn inject_latency(flag: &FlagValue) -> u64 {
+    let injected_latency = sample_latency(flag, &mut rand::thread_rng());
     let ms = injected_latency.floor() as u64;
+    let phase = flag.phase.clone().unwrap_or_default();
+    let (pre_ms, post_ms) = split_latency(ms, &phase);
 
     info!(
         source = "failure-lambda",
         mode = "latency",
         action = "inject",
         latency_ms = ms,
-        min_latency = min_latency,
-        max_latency = max_latency,
+        pre_handler_ms = pre_ms,
+        post_handler_ms = post_ms,
+        distribution = ?flag.distribution.clone().unwrap_or_default(),
+        min_latency = flag.min_latency.unwrap_or(0.0),
+        max_latency = flag.max_latency.unwrap_or(0.0),
     );
 
-    tokio::time::sleep(tokio::time::Duration::from_millis(ms)).await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(pre_ms)).await;
+    post_ms
 }
 
-/// Inject timeout by sleeping until `deadline_ms` minus `timeout_buffer_ms`, then
-/// returning normally. After this returns, the proxy forwards the event to the
-/// runtime, which begins processing — but Lambda's deadline has nearly elapsed,
-/// so Lambda kills the runtime shortly after it starts. The buffer ensures the
-/// runtime has just enough time to begin execution before the deadline hits.
-pub async fn inject_timeout(deadline_ms: u64, flag: &FlagValue) {
-    let buffer_ms = flag.timeout_buffer_ms.unwrap_or(0.0).max(0.0) as u64;
-    let now_ms = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis() as u64;
+const DEFAULT_PARETO_SHAPE: f64 = 1.16; // the 80/20 rule
 
-    let sleep_ms = if deadline_ms > now_ms + buffer_ms {
-        deadline_ms - now_ms - buffer_ms
-    } else {
-        0
+/// Sample a latency in ms. `uniform` spans [min_latency, max_latency]; other
+/// distributions are clamped to whichever bounds are set. Invalid parameters
+/// (already rejected by validation) fall back to `min_latency`.
+fn sample_latency<R: Rng + ?Sized>(flag: &FlagValue, rng: &mut R) -> f64 {
+    let min_latency = flag.min_latency.unwrap_or(0.0).max(0.0);
+    let mean = flag.mean_latency.unwrap_or(0.0);
+    let stddev = flag.stddev_latency.unwrap_or(0.0);
+
+    let sample = match flag.distribution.clone().unwrap_or_default() {
+        LatencyDistribution::Uniform => {
+            let max_latency = flag.max_latency.unwrap_or(0.0).max(0.0);
+            let range = (max_latency - min_latency).max(0.0);
+            return min_latency + rng.gen::<f64>() * range;
+        }
+        LatencyDistribution::Normal => Normal::new(mean, stddev).map(|d| d.sample(rng)).ok(),
+        LatencyDistribution::Exponential => Exp::new(1.0 / mean).map(|d| d.sample(rng)).ok(),
+        LatencyDistribution::LogNormal => LogNormal::from_mean_cv(mean, stddev / mean)
+            .map(|d| d.sample(rng))
+            .ok(),
+        LatencyDistribution::Pareto => {
+            // Pareto samples start at its scale, so `min_latency` doubles as the floor
+            let shape = flag.pareto_shape.unwrap_or(DEFAULT_PARETO_SHAPE);
+            Pareto::new(min_latency.max(1.0), shape).map(|d| d.sample(rng)).ok()
+        }
+        LatencyDistribution::Empirical => flag
+            .percentiles
+            .as_deref()
+            .and_then(|points| sample_empirical(points, flag.min_latency, flag.max_latency, rng)),
     };
 
+    let sample = sample.filter(|s| s.is_finite()).unwrap_or(min_latency);
+    let max_latency = flag.max_latency.unwrap_or(f64::INFINITY).max(min_latency);
+    sample.clamp(min_latency, max_latency)
+}
+
+/// Sample from a histogram given as percentile points, interpolating linearly
+/// between them. The 0th and 100th percentiles default to `min_latency` and
+/// `max_latency`, or to the first and last points' latencies.
+fn sample_empirical<R: Rng + ?Sized>(
+    points: &[LatencyPercentile],
+    min_latency: Option<f64>,
+    max_latency: Option<f64>,
+    rng: &mut R,
+) -> Option<f64> {
+    let mut points: Vec<(f64, f64)> = points.iter().map(|p| (p.percentile, p.latency)).collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (first, last) = (*points.first()?, *points.last()?);
+    if first.0 > 0.0 {
+        points.insert(0, (0.0, min_latency.unwrap_or(first.1).min(first.1)));
+    }
+    if last.0 < 100.0 {
+        points.push((100.0, max_latency.unwrap_or(last.1).max(last.1)));
+    }
+
+    let percentile = rng.gen::<f64>() * 100.0;
+    let upper = points
+        .iter()
+        .position(|&(p, _)| p >= percentile)
+        .unwrap_or(points.len() - 1)
+        .max(1);
+    let (p0, l0) = points[upper - 1];
+    let (p1, l1) = points[upper];
+    if p1 <= p0 {
+        return Some(l1);
+    }
+    Some(l0 + (l1 - l0) * (percentile - p0) / (p1 - p0))
+}
+
+/// Hold the runtime's `/response` or `/error` post for the post-handler share
+/// of an injected latency. No-op for zero.
+pub async fn inject_response_latency(ms: u64, endpoint: &str) {
+    if ms == 0 {
+        return;
+    }
+
+    info!(
+        source = "failure-lambda",
+        mode = "latency",
+        action = "inject_post_handler",
+        latency_ms = ms,
+        endpoint = endpoint,
+    );
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(ms)).await;
+}
+
+/// Divide a latency between the pre- and post-handler phases.
+fn split_latency(ms: u64, phase: &Phase) -> (u64, u64) {
+    match phase {
+        Phase::Pre => (ms, 0),
+        Phase::Post => (0, ms),
+        Phase::Split => (ms - ms / 2, ms / 2),
+    }
+}
+
+/// Inject timeout according to `timeout_mode`. By default, sleeps until
+/// `deadline_ms` minus `timeout_buffer_ms`, then returns normally. After this
+/// returns, the proxy forwards the event to the runtime, which begins
+/// processing — but Lambda's deadline has nearly elapsed, so Lambda kills the
+/// runtime shortly after it starts. The buffer ensures the runtime has just
+/// enough time to begin execution before the deadline hits.
+///
+/// `fraction` sleeps for `timeout_percentage` of the remaining time instead,
+/// and `hang` never returns, so the event is never forwarded. `response` is a
+/// post-handler mode, see `inject_response_timeout`.
+pub async fn inject_timeout(deadline_ms: u64, flag: &FlagValue) {
+    let timeout_mode = flag.timeout_mode.clone().unwrap_or_default();
+    let sleep_ms = timeout_sleep_ms(deadline_ms, now_ms(), flag);
+
     info!(
         source = "failure-lambda",
         mode = "timeout",
         action = "inject",
+        timeout_mode = ?timeout_mode,
+        sleep_ms = sleep_ms,
+        buffer_ms = flag.timeout_buffer_ms.unwrap_or(0.0).max(0.0) as u64,
+        deadline_ms = deadline_ms,
+    );
+
+    match sleep_ms {
+        Some(ms) => tokio::time::sleep(tokio::time::Duration::from_millis(ms)).await,
+        None => std::future::pending::<()>().await,
+    }
+}
+
+/// How far past the deadline the `response` timeout mode holds the response,
+/// so it can't race Lambda's own enforcement.
+const RESPONSE_TIMEOUT_OVERSHOOT_MS: u64 = 1000;
+
+/// Hold the handler's response until past the invocation deadline
+/// (`response` timeout mode). The handler has run to completion; Lambda times
+/// the invocation out before its response is delivered.
+pub async fn inject_response_timeout(deadline_ms: u64) {
+    let sleep_ms = (deadline_ms + RESPONSE_TIMEOUT_OVERSHOOT_MS).saturating_sub(now_ms());
+
+    info!(
+        source = "failure-lambda",
+        mode = "timeout",
+        action = "inject_post_handler",
+        timeout_mode = ?TimeoutMode::Response,
         sleep_ms = sleep_ms,
-        buffer_ms = buffer_ms,
         deadline_ms = deadline_ms,
     );
 
     tokio::time::sleep(tokio::time::Duration::from_millis(sleep_ms)).await;
 }
 
+/// Time to hold the event before forwarding it, or `None` to hold it forever.
+fn timeout_sleep_ms(deadline_ms: u64, now_ms: u64, flag: &FlagValue) -> Option<u64> {
+    let remaining_ms = deadline_ms.saturating_sub(now_ms);
+    match flag.timeout_mode.clone().unwrap_or_default() {
+        TimeoutMode::Deadline => {
+            let buffer_ms = flag.timeout_buffer_ms.unwrap_or(0.0).max(0.0) as u64;
+            Some(remaining_ms.saturating_sub(buffer_ms))
+        }
+        TimeoutMode::Fraction => {
+            let pct = flag.timeout_percentage.unwrap_or(100.0).clamp(0.0, 100.0);
+            Some((remaining_ms as f64 * pct / 100.0).floor() as u64)
+        }
+        TimeoutMode::Hang => None,
+        TimeoutMode::Response => Some(0),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
 const DISKSPACE_PREFIX: &str = "diskspace-failure-";
 const CHUNK_SIZE: usize = 1024 * 1024; // 1MB
 
@@ -148,211 +307,2202 @@ pub fn clear_diskspace() {
     }
 }
 
-/// Build the exception error payload. The caller posts this to the real API's
-/// `/invocation/{id}/error` endpoint.
-pub fn build_exception_payload(flag: &FlagValue) -> serde_json::Value {
-    let message = flag
-        .exception_msg
+/// Render `deny_list` as DNS intercept rules of the form
+/// `deny\tresult=<EAI_*>[\terrno=<n>]\t<pattern>`, or
+/// `rewrite\taddress=<ip>...\t<pattern>` for entries resolving to synthetic
+/// addresses. The LD_PRELOAD library returns the result (or the addresses)
+/// from `getaddrinfo` for matching hostnames. An entry `percentage` becomes a
+/// `percentage=<n>` option, rolled by the library on every lookup.
+///
+/// With `allow_list`, every other hostname is denied: `allow\t<pattern>` rules
+/// (which take precedence over deny rules) are written for the allowed
+/// patterns, localhost, IP literals and the Runtime API host, followed by a
+/// catch-all deny after the `deny_list` entries.
+pub fn denylist_rules(flag: &FlagValue, runtime_api: &str) -> Vec<String> {
+    let mut rules: Vec<String> = flag
+        .deny_list
         .as_deref()
-        .unwrap_or("Injected exception");
+        .unwrap_or_default()
+        .iter()
+        .map(|entry| deny_rule_line(&entry.rule()))
+        .collect();
+    let pattern_count = rules.len();
+
+    let allow_count = match flag.allow_list {
+        Some(ref patterns) => {
+            let runtime_api_host = runtime_api
+                .rsplit_once(':')
+                .map_or(runtime_api, |(host, _)| host);
+            let implicit = [
+                "^localhost$".to_string(),
+                IPV4_LITERAL_PATTERN.to_string(),
+                IPV6_LITERAL_PATTERN.to_string(),
+                format!("^{}$", regex::escape(runtime_api_host)),
+            ];
+            let allowed: Vec<String> = patterns.iter().cloned().chain(implicit).collect();
+            let allow_count = allowed.len();
+            rules.splice(0..0, allowed.into_iter().map(|pattern| format!("allow\t{pattern}")));
+            rules.push(format!("deny\tresult={}\t.", dns_result_name(&DnsResult::Noname)));
+            allow_count
+        }
+        None => 0,
+    };
 
     info!(
         source = "failure-lambda",
-        mode = "exception",
+        mode = "denylist",
         action = "inject",
-        exception_msg = message,
+        pattern_count = pattern_count,
+        allow_count = allow_count,
     );
 
-    serde_json::json!({
-        "errorMessage": message,
-        "errorType": "FailureLambdaException",
-    })
+    rules
 }
 
-/// Build the statuscode response payload. The caller posts this to the real API's
-/// `/invocation/{id}/response` endpoint.
-pub fn build_statuscode_payload(flag: &FlagValue) -> serde_json::Value {
-    let status_code = flag.status_code.unwrap_or(500);
+/// IP literals never reach DNS, so an allowlist doesn't block them.
+const IPV4_LITERAL_PATTERN: &str = "^[0-9]+(\\.[0-9]+){3}$";
+const IPV6_LITERAL_PATTERN: &str = ":";
+
+const DEFAULT_DNS_ERRNO: i32 = 5; // EIO
+
+/// TEST-NET-1 and IPv6 documentation addresses: never routed, so connections
+/// to them hang until the client times out.
+const BLACKHOLE_ADDRESSES: &[&str] = &["192.0.2.1", "2001:db8::1"];
+
+fn deny_rule_line(rule: &DenyRule) -> String {
+    let mut line = if rule.is_rewrite() {
+        rewrite_rule_options(rule)
+    } else {
+        let result = rule.result.clone().unwrap_or_default();
+        let mut line = format!("deny\tresult={}", dns_result_name(&result));
+        if result == DnsResult::System {
+            line.push_str(&format!("\terrno={}", rule.errno.unwrap_or(DEFAULT_DNS_ERRNO)));
+        }
+        line
+    };
+    if let Some(pct) = rule.percentage.filter(|pct| *pct < 100) {
+        line.push_str(&format!("\tpercentage={pct}"));
+    }
+    line.push('\t');
+    line.push_str(&rule.pattern);
+    line
+}
+
+fn rewrite_rule_options(rule: &DenyRule) -> String {
+    let addresses: Vec<&str> = match rule.addresses {
+        Some(ref addresses) => addresses.iter().map(String::as_str).collect(),
+        None => BLACKHOLE_ADDRESSES.to_vec(),
+    };
+    let mut line = "rewrite".to_string();
+    for address in addresses {
+        let is_ipv6 = address.contains(':');
+        let keep = match rule.family {
+            Some(AddressFamily::Ipv4) => !is_ipv6,
+            Some(AddressFamily::Ipv6) => is_ipv6,
+            None => true,
+        };
+        if keep {
+            line.push_str(&format!("\taddress={address}"));
+        }
+    }
+    line
+}
+
+fn dns_result_name(result: &DnsResult) -> &'static str {
+    match result {
+        DnsResult::Noname => "EAI_NONAME",
+        DnsResult::Again => "EAI_AGAIN",
+        DnsResult::Fail => "EAI_FAIL",
+        DnsResult::System => "EAI_SYSTEM",
+        DnsResult::Blackhole => "BLACKHOLE",
+    }
+}
+
+/// Render `dns_delays` as DNS intercept rules of the form
+/// `delay\tmin=<ms>\tmax=<ms>\t<pattern>`. The LD_PRELOAD library sleeps for a
+/// random delay in [min, max] before resolving a matching hostname.
+pub fn dns_latency_rules(flag: &FlagValue) -> Vec<String> {
+    let rules: Vec<String> = flag
+        .dns_delays
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .map(|delay| {
+            let min_ms = delay.min_latency.max(0.0).floor() as u64;
+            let max_ms = delay.max_latency.unwrap_or(delay.min_latency).max(0.0).floor() as u64;
+            format!("delay\tmin={min_ms}\tmax={}\t{}", max_ms.max(min_ms), delay.pattern)
+        })
+        .collect();
 
     info!(
         source = "failure-lambda",
-        mode = "statuscode",
+        mode = "dns_latency",
         action = "inject",
-        status_code = status_code,
+        pattern_count = rules.len(),
     );
 
-    serde_json::json!({
-        "statusCode": status_code,
-        "headers": { "Content-Type": "application/json" },
-        "body": format!("{{\"message\":\"Injected status code {status_code}\"}}")
-    })
+    rules
 }
 
-/// Corrupt a response body. If `flag.body` is set, replaces the body entirely.
-/// Otherwise, mangles it by truncating and appending replacement characters.
-pub fn corrupt_response(flag: &FlagValue, body: &str) -> String {
-    if let Some(ref replacement) = flag.body {
-        info!(
-            source = "failure-lambda",
-            mode = "corruption",
-            action = "inject",
-            method = "replace",
-        );
-        // Try to parse as JSON and replace the body field
-        if let Ok(mut json) = serde_json::from_str::<serde_json::Value>(body) {
-            if let Some(obj) = json.as_object_mut() {
-                if obj.contains_key("body") {
-                    obj.insert("body".to_string(), serde_json::Value::String(replacement.clone()));
-                    return serde_json::to_string(&json).unwrap_or_else(|_| body.to_string());
-                }
+/// Render `connect_rules` as connect intercept rules of the form
+/// `<action>[\tports=<p,p>][\tpercentage=<n>]\t<cidr>`, with `min`/`max`
+/// options for `delay`. The LD_PRELOAD library checks them on every outbound
+/// `connect()`, which also catches IP literals and addresses resolved before
+/// the denylist was written.
+pub fn connection_rules(flag: &FlagValue) -> Vec<String> {
+    let rules: Vec<String> = flag
+        .connect_rules
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .map(connect_rule_line)
+        .collect();
+
+    info!(
+        source = "failure-lambda",
+        mode = "connection",
+        action = "inject",
+        rule_count = rules.len(),
+    );
+
+    rules
+}
+
+fn connect_rule_line(rule: &ConnectRule) -> String {
+    let mut line = match rule.action {
+        ConnectAction::Refuse => "refuse".to_string(),
+        ConnectAction::Reset => "reset".to_string(),
+        ConnectAction::Timeout => "timeout".to_string(),
+        ConnectAction::Delay => {
+            let min_latency = rule.min_latency.unwrap_or(0.0);
+            let min_ms = min_latency.max(0.0).floor() as u64;
+            let max_ms = rule.max_latency.unwrap_or(min_latency).max(0.0).floor() as u64;
+            format!("delay\tmin={min_ms}\tmax={}", max_ms.max(min_ms))
+        }
+    };
+    if let Some(ref ports) = rule.ports {
+        let ports: Vec<String> = ports.iter().map(u16::to_string).collect();
+        line.push_str(&format!("\tports={}", ports.join(",")));
+    }
+    if let Some(percentage) = rule.percentage.filter(|pct| *pct < 100) {
+        line.push_str(&format!("\tpercentage={percentage}"));
+    }
+    format!("{line}\t{}", rule.cidr)
+}
+
+const DEFAULT_CPU_THREADS: u32 = 1;
+const DEFAULT_CPU_DUTY_CYCLE: u32 = 100;
+/// Busy/idle scheduling period of a `cpu` thread.
+const CPU_PERIOD: Duration = Duration::from_millis(10);
+
+/// Busy threads started by `cpu`. They run until this is dropped, which
+/// happens when the invocation's state is removed on `/response` or `/error`.
+pub struct CpuLoad {
+    stop: Arc<AtomicBool>,
+    /// Busy/idle periods completed across all threads.
+    periods: Arc<AtomicU64>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl Drop for CpuLoad {
+    fn drop(&mut self) {
+        // Threads stop spinning within one period of seeing the flag, but a
+        // CPU-throttled sandbox can take much longer to get them there, so
+        // inside the runtime the join runs on a blocking thread rather than
+        // holding up the Runtime API path (or a lock the caller holds)
+        self.stop.store(true, Ordering::Relaxed);
+        let threads = std::mem::take(&mut self.threads);
+        let periods = Arc::clone(&self.periods);
+        let join = move || {
+            for handle in threads {
+                let _ = handle.join();
             }
-            // Response has no body field; wrap in { body }
-            warn!(
+            info!(
                 source = "failure-lambda",
-                mode = "corruption",
-                message = "response has no body field; wrapping in {{ body }}",
+                mode = "cpu",
+                action = "clear",
+                periods = periods.load(Ordering::Relaxed),
             );
-            return serde_json::json!({ "body": replacement }).to_string();
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn_blocking(join);
+            }
+            Err(_) => join(),
         }
-        return replacement.clone();
     }
+}
+
+/// Start `cpu_threads` threads that each spin for `cpu_duty_cycle`% of every
+/// period, competing with the handler for the sandbox's vCPU share.
+pub fn inject_cpu(flag: &FlagValue) -> CpuLoad {
+    let threads = flag.cpu_threads.unwrap_or(DEFAULT_CPU_THREADS).max(1);
+    let duty_cycle = flag.cpu_duty_cycle.unwrap_or(DEFAULT_CPU_DUTY_CYCLE).clamp(1, 100);
+    let busy = CPU_PERIOD * duty_cycle / 100;
+    let stop = Arc::new(AtomicBool::new(false));
+    let periods = Arc::new(AtomicU64::new(0));
+    let mut handles = Vec::new();
 
     info!(
         source = "failure-lambda",
-        mode = "corruption",
+        mode = "cpu",
         action = "inject",
-        method = "mangle",
+        cpu_threads = threads,
+        cpu_duty_cycle = duty_cycle,
     );
 
-    // Try to parse as JSON and mangle the body field
-    if let Ok(mut json) = serde_json::from_str::<serde_json::Value>(body) {
-        if let Some(obj) = json.as_object_mut() {
-            if let Some(serde_json::Value::String(ref body_str)) = obj.get("body").cloned() {
-                let mangled = mangle_string(body_str);
-                obj.insert("body".to_string(), serde_json::Value::String(mangled));
-                return serde_json::to_string(&json).unwrap_or_else(|_| body.to_string());
+    for i in 0..threads {
+        let stop = Arc::clone(&stop);
+        let periods = Arc::clone(&periods);
+        let spawned = thread::Builder::new()
+            .name(format!("failure-lambda-cpu-{i}"))
+            .spawn(move || spin_cpu(&stop, &periods, busy));
+        match spawned {
+            Ok(handle) => handles.push(handle),
+            Err(e) => {
+                error!(
+                    source = "failure-lambda",
+                    mode = "cpu",
+                    action = "error",
+                    message = format!("failed to start cpu thread: {e}"),
+                );
+                break;
             }
         }
     }
 
-    warn!(
+    CpuLoad { stop, periods, threads: handles }
+}
+
+fn spin_cpu(stop: &AtomicBool, periods: &AtomicU64, busy: Duration) {
+    while !stop.load(Ordering::Relaxed) {
+        let start = Instant::now();
+        while start.elapsed() < busy {
+            std::hint::spin_loop();
+        }
+        if busy < CPU_PERIOD {
+            thread::sleep(CPU_PERIOD - busy);
+        }
+        periods.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+const MB: usize = 1024 * 1024;
+
+/// Allocate and touch memory for `memory`. Without `memory_leak`, returns an
+/// allocation held for the invocation and released with its state. With it,
+/// grows `leaked` by the configured amount (up to `memory_leak_max_mb`, or the
+/// function's memory size) and returns `None`; `leaked` lives for the whole
+/// execution environment.
+pub fn inject_memory(flag: &FlagValue, leaked: &mut Vec<Vec<u8>>) -> Option<Vec<u8>> {
+    let function_memory_mb = std::env::var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok());
+    let Some(target_mb) = memory_target_mb(flag, function_memory_mb) else {
+        warn!(
+            source = "failure-lambda",
+            mode = "memory",
+            action = "skip",
+            message = "memory_percentage needs AWS_LAMBDA_FUNCTION_MEMORY_SIZE; nothing allocated",
+        );
+        return None;
+    };
+    let leak = flag.memory_leak.unwrap_or(false);
+
+    let (allocation_mb, allocation) = if leak {
+        let cap_mb = flag
+            .memory_leak_max_mb
+            .map(u64::from)
+            .or(function_memory_mb)
+            .unwrap_or(target_mb);
+        let grow_mb = target_mb.min(cap_mb.saturating_sub(held_mb(leaked)));
+        if grow_mb > 0 {
+            if let Some(block) = allocate_memory(grow_mb) {
+                leaked.push(block);
+            }
+        }
+        (0, None)
+    } else {
+        let allocation = allocate_memory(target_mb);
+        (allocation.as_ref().map_or(0, |a| (a.len() / MB) as u64), allocation)
+    };
+
+    info!(
         source = "failure-lambda",
-        mode = "corruption",
-        message = "response has no string body field to mangle; returning unchanged",
+        mode = "memory",
+        action = "inject",
+        memory_leak = leak,
+        target_mb = target_mb,
+        allocated_mb = allocation_mb + held_mb(leaked),
+        leaked_mb = held_mb(leaked),
     );
-    body.to_string()
+
+    allocation
 }
 
-fn mangle_string(input: &str) -> String {
-    if input.is_empty() {
-        return input.to_string();
+/// Report memory still held by `memory_leak` on an invocation where the
+/// memory flag didn't fire, so every invocation logs the current level.
+pub fn report_leaked_memory(leaked: &[Vec<u8>]) {
+    let leaked_mb = held_mb(leaked);
+    if leaked_mb > 0 {
+        info!(
+            source = "failure-lambda",
+            mode = "memory",
+            action = "hold",
+            leaked_mb = leaked_mb,
+        );
     }
-    let truncate_fraction = 0.3 + rand::thread_rng().gen::<f64>() * 0.5;
-    let truncate_point = (input.len() as f64 * truncate_fraction).floor() as usize;
-    // Ensure we don't split a multi-byte character
-    let safe_point = input
-        .char_indices()
-        .take_while(|&(i, _)| i <= truncate_point)
-        .last()
-        .map(|(i, _)| i)
-        .unwrap_or(0);
-    let mut result = input[..safe_point].to_string();
-    result.push_str("\u{FFFD}\u{FFFD}\u{FFFD}");
-    result
 }
 
-/// Resolve a dot-separated path against a nested JSON value.
-pub fn get_nested_value<'a>(obj: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
-    let mut current = obj;
-    for part in path.split('.') {
-        match current.get(part) {
-            Some(v) => current = v,
-            None => return None,
-        }
+/// MB to allocate: `memory_mb`, or `memory_percentage` of the function's
+/// memory size. `None` when the percentage can't be resolved.
+fn memory_target_mb(flag: &FlagValue, function_memory_mb: Option<u64>) -> Option<u64> {
+    match (flag.memory_mb, flag.memory_percentage, function_memory_mb) {
+        (Some(mb), _, _) => Some(u64::from(mb)),
+        (None, Some(pct), Some(function_mb)) => Some(function_mb * u64::from(pct.min(100)) / 100),
+        _ => None,
     }
-    Some(current)
 }
 
-// Cache for compiled regex patterns used in match conditions.
-thread_local! {
-    static REGEX_CACHE: std::cell::RefCell<HashMap<String, Regex>> =
-        std::cell::RefCell::new(HashMap::new());
+fn held_mb(blocks: &[Vec<u8>]) -> u64 {
+    (blocks.iter().map(Vec::len).sum::<usize>() / MB) as u64
 }
 
-fn get_cached_regex(pattern: &str) -> Option<Regex> {
-    REGEX_CACHE.with(|cache| {
-        let mut cache = cache.borrow_mut();
-        if let Some(re) = cache.get(pattern) {
-            return Some(re.clone());
-        }
-        match Regex::new(pattern) {
-            Ok(re) => {
-                cache.insert(pattern.to_string(), re.clone());
-                Some(re)
-            }
-            Err(_) => None,
+/// Allocate `mb` MB and write every byte so the pages are resident. Reports
+/// and returns `None` if the allocation is refused, instead of aborting.
+fn allocate_memory(mb: u64) -> Option<Vec<u8>> {
+    let bytes = mb as usize * MB;
+    let mut block = Vec::new();
+    if let Err(e) = block.try_reserve_exact(bytes) {
+        error!(
+            source = "failure-lambda",
+            mode = "memory",
+            action = "error",
+            message = format!("failed to allocate {mb} MB: {e}"),
+        );
+        return None;
+    }
+    block.resize(bytes, 0xa5);
+    Some(block)
+}
+
+/// Apply the flag's mutations to a copy of the event. Returns `None` when the
+/// event is not a JSON object or array (e.g. a raw string payload), leaving the
+/// original bytes to be forwarded untouched.
+pub fn mutate_event(flag: &FlagValue, event: &serde_json::Value) -> Option<serde_json::Value> {
+    if !event.is_object() && !event.is_array() {
+        warn!(
+            source = "failure-lambda",
+            mode = "event_mutation",
+            message = "event is not a JSON object or array; skipping mutation",
+        );
+        return None;
+    }
+
+    let mut mutated = event.clone();
+    let mut applied = 0;
+    for mutation in flag.mutations.as_deref().unwrap_or_default() {
+        if apply_mutation(&mut mutated, mutation) {
+            applied += 1;
+        } else {
+            warn!(
+                source = "failure-lambda",
+                mode = "event_mutation",
+                path = %mutation.path,
+                message = "path not found or has an incompatible type; skipping",
+            );
         }
-    })
+    }
+
+    info!(
+        source = "failure-lambda",
+        mode = "event_mutation",
+        action = "inject",
+        mutations_applied = applied,
+    );
+
+    Some(mutated)
 }
 
-/// Evaluate a single match operator against an actual JSON value.
-fn match_operator(actual: Option<&serde_json::Value>, operator: &MatchOperator, value: Option<&str>) -> bool {
-    match operator {
-        MatchOperator::Exists => actual.is_some() && !actual.unwrap().is_null(),
-        MatchOperator::StartsWith => {
-            match actual {
-                Some(v) if !v.is_null() => {
-                    let actual_str = json_value_to_string(v);
-                    actual_str.starts_with(value.unwrap_or(""))
-                }
-                _ => false,
-            }
+/// Apply a single mutation. Returns false if the path could not be resolved or
+/// the operation does not apply to the value found there.
+fn apply_mutation(event: &mut serde_json::Value, mutation: &EventMutation) -> bool {
+    use serde_json::Value;
+    match mutation.operation {
+        MutationOperation::Delete => delete_path(event, &mutation.path),
+        MutationOperation::Set => {
+            set_path(event, &mutation.path, mutation.value.clone().unwrap_or(Value::Null))
         }
-        MatchOperator::Regex => {
-            match actual {
-                Some(v) if !v.is_null() => {
-                    let actual_str = json_value_to_string(v);
-                    match get_cached_regex(value.unwrap_or("")) {
-                        Some(re) => re.is_match(&actual_str),
-                        None => false,
-                    }
-                }
-                _ => false,
+        MutationOperation::ChangeType => match get_nested_value_mut(event, &mutation.path) {
+            Some(target) => {
+                let to = mutation.to.clone().unwrap_or(if target.is_string() {
+                    JsonType::Number
+                } else {
+                    JsonType::String
+                });
+                *target = convert_json_type(target, &to);
+                true
             }
-        }
-        MatchOperator::Eq => {
-            match actual {
-                Some(v) if !v.is_null() => {
-                    let actual_str = json_value_to_string(v);
-                    actual_str == value.unwrap_or("")
+            None => false,
+        },
+        MutationOperation::Truncate => match get_nested_value_mut(event, &mutation.path) {
+            Some(Value::String(s)) => {
+                let char_count = s.chars().count();
+                let length = mutation.length.unwrap_or(char_count / 2);
+                *s = s.chars().take(length).collect();
+                true
+            }
+            Some(Value::Array(arr)) => {
+                let length = mutation.length.unwrap_or(arr.len() / 2);
+                arr.truncate(length);
+                true
+            }
+            _ => false,
+        },
+        MutationOperation::DropElements => match get_nested_value_mut(event, &mutation.path) {
+            Some(Value::Array(arr)) => {
+                let count = mutation.count.unwrap_or(1).min(arr.len());
+                for _ in 0..count {
+                    let index = rand::thread_rng().gen_range(0..arr.len());
+                    arr.remove(index);
                 }
-                _ => false,
+                true
             }
-        }
+            _ => false,
+        },
     }
 }
 
-fn json_value_to_string(v: &serde_json::Value) -> String {
-    match v {
-        serde_json::Value::String(s) => s.clone(),
-        serde_json::Value::Number(n) => n.to_string(),
+/// Remove the field or array element at `path`.
+fn delete_path(event: &mut serde_json::Value, path: &str) -> bool {
+    let (parent_path, key) = match path.rsplit_once('.') {
+        Some((parent, key)) => (Some(parent), key),
+        None => (None, path),
+    };
+    let parent = match parent_path {
+        Some(p) => get_nested_value_mut(event, p),
+        None => Some(event),
+    };
+    match parent {
+        Some(serde_json::Value::Object(obj)) => obj.remove(key).is_some(),
+        Some(serde_json::Value::Array(arr)) => match key.parse::<usize>() {
+            Ok(i) if i < arr.len() => {
+                arr.remove(i);
+                true
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Set the value at `path`, creating missing intermediate objects. Returns
+/// false if the path runs through a scalar or past the end of an array.
+fn set_path(event: &mut serde_json::Value, path: &str, value: serde_json::Value) -> bool {
+    let mut current = event;
+    let mut parts = path.split('.').peekable();
+    while let Some(part) = parts.next() {
+        let last = parts.peek().is_none();
+        current = match current {
+            serde_json::Value::Object(map) => {
+                if last {
+                    map.insert(part.to_string(), value);
+                    return true;
+                }
+                map.entry(part)
+                    .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()))
+            }
+            serde_json::Value::Array(arr) => {
+                let Some(slot) = part.parse::<usize>().ok().and_then(|i| arr.get_mut(i)) else {
+                    return false;
+                };
+                if last {
+                    *slot = value;
+                    return true;
+                }
+                slot
+            }
+            _ => return false,
+        };
+    }
+    false
+}
+
+/// Convert a JSON value to another type, keeping as much of the original as
+/// makes sense (e.g. "42" becomes 42, an object becomes its serialized form).
+fn convert_json_type(value: &serde_json::Value, to: &JsonType) -> serde_json::Value {
+    use serde_json::Value;
+    match to {
+        JsonType::String => Value::String(json_value_to_string(value)),
+        JsonType::Number => match value {
+            Value::Number(_) => value.clone(),
+            // Integers stay integers: "25" becomes 25, not 25.0
+            Value::String(s) => {
+                let s = s.trim();
+                s.parse::<i64>()
+                    .map(Value::from)
+                    .ok()
+                    .or_else(|| {
+                        s.parse::<f64>()
+                            .ok()
+                            .and_then(serde_json::Number::from_f64)
+                            .map(Value::Number)
+                    })
+                    .unwrap_or_else(|| Value::from(0))
+            }
+            Value::Bool(b) => Value::from(*b as u8),
+            Value::Array(arr) => Value::from(arr.len()),
+            Value::Object(obj) => Value::from(obj.len()),
+            Value::Null => Value::from(0),
+        },
+        JsonType::Boolean => Value::Bool(match value {
+            Value::Bool(b) => *b,
+            Value::Number(n) => n.as_f64().is_some_and(|f| f != 0.0),
+            Value::String(s) => !s.is_empty(),
+            Value::Array(_) | Value::Object(_) => true,
+            Value::Null => false,
+        }),
+        JsonType::Array => match value {
+            Value::Array(_) => value.clone(),
+            other => Value::Array(vec![other.clone()]),
+        },
+        JsonType::Object => match value {
+            Value::Object(_) => value.clone(),
+            other => serde_json::json!({ "value": other }),
+        },
+        JsonType::Null => Value::Null,
+    }
+}
+
+const DEFAULT_ERROR_TYPE: &str = "FailureLambdaException";
+
+/// Build the exception error payload. The caller posts this to the real API's
+/// `/invocation/{id}/error` endpoint, in the post phase discarding the
+/// handler's response. A `runtime` preset shapes it like that runtime's own
+/// error reports; `error_type` and `stack_trace` override the preset.
+pub fn build_exception_payload(flag: &FlagValue) -> serde_json::Value {
+    let message = flag
+        .exception_msg
+        .as_deref()
+        .unwrap_or("Injected exception");
+    let error_type = exception_error_type(flag);
+    let stack_trace = exception_stack_trace(flag, error_type, message);
+    let phase = flag.phase.clone().unwrap_or_default();
+
+    info!(
+        source = "failure-lambda",
+        mode = "exception",
+        action = "inject",
+        exception_msg = message,
+        error_type = error_type,
+        runtime = ?flag.runtime,
+        phase = ?phase,
+    );
+
+    let mut payload = serde_json::json!({
+        "errorMessage": message,
+        "errorType": error_type,
+    });
+    if !stack_trace.is_empty() {
+        // The Node.js runtime reports frames under `trace`; the others use `stackTrace`
+        let key = match flag.runtime {
+            Some(ErrorRuntime::Node) => "trace",
+            _ => "stackTrace",
+        };
+        payload[key] = serde_json::json!(stack_trace);
+    }
+    payload
+}
+
+/// The `errorType` reported for an injected exception, also sent as the
+/// `Lambda-Runtime-Function-Error-Type` header.
+pub fn exception_error_type(flag: &FlagValue) -> &str {
+    if let Some(error_type) = flag.error_type.as_deref() {
+        return error_type;
+    }
+    match flag.runtime {
+        Some(ErrorRuntime::Node) => "Error",
+        Some(ErrorRuntime::Python) => "Exception",
+        Some(ErrorRuntime::Java) => "java.lang.RuntimeException",
+        None => DEFAULT_ERROR_TYPE,
+    }
+}
+
+/// The configured stack trace, or the runtime preset's frames.
+fn exception_stack_trace(flag: &FlagValue, error_type: &str, message: &str) -> Vec<String> {
+    if let Some(ref frames) = flag.stack_trace {
+        return frames.clone();
+    }
+    match flag.runtime {
+        Some(ErrorRuntime::Node) => vec![
+            format!("{error_type}: {message}"),
+            "    at Runtime.handler (file:///var/task/index.mjs:3:9)".to_string(),
+            "    at Runtime.handleOnceNonStreaming (file:///var/runtime/index.mjs:1173:29)".to_string(),
+        ],
+        Some(ErrorRuntime::Python) => vec![format!(
+            "  File \"/var/task/lambda_function.py\", line 3, in lambda_handler\n    raise {error_type}({message:?})\n"
+        )],
+        Some(ErrorRuntime::Java) => vec![
+            "example.Handler.handleRequest(Handler.java:12)".to_string(),
+            "java.base/jdk.internal.reflect.DirectMethodHandleAccessor.invoke(Unknown Source)".to_string(),
+            "java.base/java.lang.reflect.Method.invoke(Unknown Source)".to_string(),
+        ],
+        None => Vec::new(),
+    }
+}
+
+/// Build the `Lambda-Runtime-Function-XRay-Error-Cause` header value for an
+/// exception payload, so the injected error shows up as a fault in X-Ray.
+/// Non-ASCII characters are escaped, as header values must be visible ASCII.
+pub fn build_xray_error_cause(payload: &serde_json::Value) -> String {
+    let frames = payload
+        .get("stackTrace")
+        .or_else(|| payload.get("trace"))
+        .and_then(|t| t.as_array())
+        .map(|frames| {
+            frames
+                .iter()
+                .filter_map(|f| f.as_str())
+                .map(|f| serde_json::json!({ "label": f.trim() }))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let working_directory =
+        std::env::var("LAMBDA_TASK_ROOT").unwrap_or_else(|_| "/var/task".to_string());
+
+    let cause = serde_json::json!({
+        "working_directory": working_directory,
+        "exceptions": [{
+            "type": payload["errorType"],
+            "message": payload["errorMessage"],
+            "stack": frames,
+        }],
+        "paths": [],
+    });
+
+    let mut header = String::new();
+    for c in cause.to_string().chars() {
+        if c.is_ascii() && c != '\x7f' {
+            header.push(c);
+        } else {
+            let mut units = [0u16; 2];
+            for unit in c.encode_utf16(&mut units) {
+                header.push_str(&format!("\\u{unit:04x}"));
+            }
+        }
+    }
+    header
+}
+
+/// What an injected status code reports to the Runtime API.
+pub enum StatusCodeInjection {
+    /// A response payload for `/invocation/{id}/response`.
+    Response(serde_json::Value),
+    /// An exception for `/invocation/{id}/error`. AppSync direct Lambda
+    /// resolvers take any returned payload as the field's data, and only
+    /// report a failed invocation in `errors`, with its `errorType` and
+    /// message.
+    Error(Box<FlagValue>),
+}
+
+/// Build the statuscode injection. The caller posts a response payload to the
+/// real API's `/invocation/{id}/response` endpoint, or in the post phase
+/// forwards it in place of the handler's response; an error goes to `/error`.
+/// The shape follows the event source detected from `event` unless
+/// `response_format` overrides it.
+pub fn build_statuscode_injection(flag: &FlagValue, event: &serde_json::Value) -> StatusCodeInjection {
+    let status_code = flag.status_code.unwrap_or(500);
+    let phase = flag.phase.clone().unwrap_or_default();
+    let format = flag
+        .response_format
+        .clone()
+        .unwrap_or_else(|| detect_response_format(event));
+
+    info!(
+        source = "failure-lambda",
+        mode = "statuscode",
+        action = "inject",
+        status_code = status_code,
+        phase = ?phase,
+        response_format = ?format,
+    );
+
+    let body = match flag.body {
+        Some(ref body) => body.clone(),
+        None => format!("{{\"message\":\"Injected status code {status_code}\"}}"),
+    };
+    let mut headers: Vec<(String, String)> = vec![(
+        "Content-Type".to_string(),
+        flag.content_type
+            .clone()
+            .unwrap_or_else(|| "application/json".to_string()),
+    )];
+    if let Some(ref custom) = flag.headers {
+        let mut custom: Vec<(&String, &String)> = custom.iter().collect();
+        custom.sort();
+        for (name, value) in custom {
+            headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+            headers.push((name.clone(), value.clone()));
+        }
+    }
+    let single_value_headers = || {
+        serde_json::Value::Object(
+            headers
+                .iter()
+                .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+                .collect(),
+        )
+    };
+    // ALB wants "502 Bad Gateway", CloudFront only the reason phrase
+    let reason = hyper::StatusCode::from_u16(status_code)
+        .ok()
+        .and_then(|s| s.canonical_reason())
+        .unwrap_or("Unknown");
+
+    let response = match format {
+        ResponseFormat::ApiGatewayV1 => serde_json::json!({
+            "statusCode": status_code,
+            "headers": single_value_headers(),
+            "body": body
+        }),
+        ResponseFormat::ApiGatewayV2 | ResponseFormat::FunctionUrl => serde_json::json!({
+            "statusCode": status_code,
+            "headers": single_value_headers(),
+            "body": body,
+            "isBase64Encoded": false
+        }),
+        ResponseFormat::Alb => {
+            // ALB rejects single-value headers when the target group has
+            // multi-value headers enabled, which the event reveals
+            let mut response = serde_json::json!({
+                "statusCode": status_code,
+                "statusDescription": format!("{status_code} {reason}"),
+                "body": body,
+                "isBase64Encoded": false
+            });
+            if event.get("multiValueHeaders").is_some() {
+                response["multiValueHeaders"] = serde_json::Value::Object(
+                    headers
+                        .iter()
+                        .map(|(k, v)| (k.clone(), serde_json::json!([v])))
+                        .collect(),
+                );
+            } else {
+                response["headers"] = single_value_headers();
+            }
+            response
+        }
+        ResponseFormat::CloudFront => serde_json::json!({
+            "status": status_code.to_string(),
+            "statusDescription": reason,
+            "headers": serde_json::Value::Object(
+                headers
+                    .iter()
+                    .map(|(k, v)| {
+                        (k.to_ascii_lowercase(), serde_json::json!([{ "key": k, "value": v }]))
+                    })
+                    .collect(),
+            ),
+            "body": body
+        }),
+        ResponseFormat::AppSync => {
+            return StatusCodeInjection::Error(Box::new(FlagValue {
+                exception_msg: Some(body),
+                error_type: Some(format!("HTTP{status_code}")),
+                ..flag.clone()
+            }));
+        }
+    };
+    StatusCodeInjection::Response(response)
+}
+
+/// Detect which response shape the invoking service expects from its event.
+/// Falls back to the API Gateway REST (v1) shape.
+pub fn detect_response_format(event: &serde_json::Value) -> ResponseFormat {
+    if get_nested_value(event, "requestContext.elb").is_some() {
+        return ResponseFormat::Alb;
+    }
+    if event
+        .get("Records")
+        .and_then(|r| r.get(0))
+        .and_then(|r| r.get("cf"))
+        .is_some()
+    {
+        return ResponseFormat::CloudFront;
+    }
+    if get_nested_value(event, "info.fieldName").is_some() && event.get("arguments").is_some() {
+        return ResponseFormat::AppSync;
+    }
+    if event.get("version").and_then(|v| v.as_str()) == Some("2.0") {
+        let is_function_url = get_nested_value(event, "requestContext.domainName")
+            .and_then(|d| d.as_str())
+            .is_some_and(|d| d.contains(".lambda-url."));
+        return if is_function_url {
+            ResponseFormat::FunctionUrl
+        } else {
+            ResponseFormat::ApiGatewayV2
+        };
+    }
+    ResponseFormat::ApiGatewayV1
+}
+
+/// Corrupt a response body using the flag's `strategy`. Without an explicit
+/// strategy, replaces the body entirely if `flag.body` is set, and otherwise
+/// mangles it by truncating and appending replacement characters.
+///
+/// Base64-encoded bodies (`isBase64Encoded: true`) are decoded, and
+/// decompressed per `Content-Encoding`, so the strategy corrupts the actual
+/// payload; the result is then re-encoded. With `break_encoding`, the encoding
+/// layer itself is broken instead.
+pub fn corrupt_response(flag: &FlagValue, body: &str) -> String {
+    let encoded = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|response| encoded_body(&response).map(|encoding| (response, encoding)));
+
+    if let Some((response, encoding)) = encoded {
+        if flag.break_encoding == Some(true) {
+            return break_body_encoding(response, &encoding);
+        }
+        // Padding compresses to almost nothing, so oversize works on the
+        // encoded response
+        if flag.strategy != Some(CorruptionStrategy::Oversize) {
+            match decode_response_body(&response, &encoding) {
+                Ok(decoded) => {
+                    let corrupted = corrupt_plain_response(flag, &decoded);
+                    return reencode_response_body(&corrupted, &encoding)
+                        .unwrap_or(corrupted);
+                }
+                Err(e) => {
+                    warn!(
+                        source = "failure-lambda",
+                        mode = "corruption",
+                        message = format!("failed to decode response body ({e}); corrupting encoded body"),
+                    );
+                }
+            }
+        }
+    }
+
+    corrupt_plain_response(flag, body)
+}
+
+fn corrupt_plain_response(flag: &FlagValue, body: &str) -> String {
+    let strategy = flag.strategy.clone().unwrap_or(if flag.body.is_some() {
+        CorruptionStrategy::Replace
+    } else {
+        CorruptionStrategy::Mangle
+    });
+
+    match strategy {
+        CorruptionStrategy::Replace => replace_body(flag.body.as_deref().unwrap_or_default(), body),
+        CorruptionStrategy::Mangle => mangle_body(body),
+        CorruptionStrategy::Oversize => oversize_body(flag, body),
+        ref field_strategy => corrupt_fields(flag, field_strategy, body),
+    }
+}
+
+fn replace_body(replacement: &str, body: &str) -> String {
+    info!(
+        source = "failure-lambda",
+        mode = "corruption",
+        action = "inject",
+        method = "replace",
+    );
+    // Try to parse as JSON and replace the body field
+    if let Ok(mut json) = serde_json::from_str::<serde_json::Value>(body) {
+        if let Some(obj) = json.as_object_mut() {
+            if obj.contains_key("body") {
+                obj.insert("body".to_string(), serde_json::Value::String(replacement.to_string()));
+                return serde_json::to_string(&json).unwrap_or_else(|_| body.to_string());
+            }
+        }
+        // Response has no body field; wrap in { body }
+        warn!(
+            source = "failure-lambda",
+            mode = "corruption",
+            message = "response has no body field; wrapping in {{ body }}",
+        );
+        return serde_json::json!({ "body": replacement }).to_string();
+    }
+    replacement.to_string()
+}
+
+fn mangle_body(body: &str) -> String {
+    info!(
+        source = "failure-lambda",
+        mode = "corruption",
+        action = "inject",
+        method = "mangle",
+    );
+
+    // Try to parse as JSON and mangle the body field
+    if let Ok(mut json) = serde_json::from_str::<serde_json::Value>(body) {
+        if let Some(obj) = json.as_object_mut() {
+            if let Some(serde_json::Value::String(ref body_str)) = obj.get("body").cloned() {
+                let mangled = mangle_string(body_str);
+                obj.insert("body".to_string(), serde_json::Value::String(mangled));
+                return serde_json::to_string(&json).unwrap_or_else(|_| body.to_string());
+            }
+        }
+    }
+
+    warn!(
+        source = "failure-lambda",
+        mode = "corruption",
+        message = "response has no string body field to mangle; returning unchanged",
+    );
+    body.to_string()
+}
+
+/// Compression applied to a response body, from its `Content-Encoding` header.
+#[derive(Debug, Clone, PartialEq)]
+enum ContentEncoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+}
+
+/// Detect a base64-encoded string body and its content encoding. Returns
+/// `None` for plain bodies and for encodings that can't be decompressed.
+fn encoded_body(response: &serde_json::Value) -> Option<ContentEncoding> {
+    if response.get("isBase64Encoded") != Some(&serde_json::Value::Bool(true))
+        || !response.get("body").is_some_and(|b| b.is_string())
+    {
+        return None;
+    }
+    let header = ["headers", "multiValueHeaders"].iter().find_map(|key| {
+        response.get(key)?.as_object()?.iter().find_map(|(name, value)| {
+            if !name.eq_ignore_ascii_case("content-encoding") {
+                return None;
+            }
+            match value {
+                serde_json::Value::String(s) => Some(s.clone()),
+                serde_json::Value::Array(values) => values.first()?.as_str().map(str::to_string),
+                _ => None,
+            }
+        })
+    });
+    match header.as_deref().map(|h| h.trim().to_ascii_lowercase()).as_deref() {
+        None | Some("identity") => Some(ContentEncoding::Identity),
+        Some("gzip") | Some("x-gzip") => Some(ContentEncoding::Gzip),
+        Some("deflate") => Some(ContentEncoding::Deflate),
+        Some("br") => Some(ContentEncoding::Brotli),
+        Some(other) => {
+            warn!(
+                source = "failure-lambda",
+                mode = "corruption",
+                message = format!("unsupported Content-Encoding \"{other}\"; treating body as opaque"),
+            );
+            None
+        }
+    }
+}
+
+/// Return the response with its body base64-decoded and decompressed, and
+/// `isBase64Encoded` cleared, as a JSON string ready for a corruption strategy.
+fn decode_response_body(response: &serde_json::Value, encoding: &ContentEncoding) -> Result<String, String> {
+    use base64::Engine;
+    use std::io::Read;
+
+    let encoded = response["body"].as_str().unwrap_or_default();
+    let compressed = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("invalid base64: {e}"))?;
+    let mut raw = Vec::new();
+    match encoding {
+        ContentEncoding::Identity => raw = compressed,
+        ContentEncoding::Gzip => {
+            flate2::read::GzDecoder::new(&compressed[..])
+                .read_to_end(&mut raw)
+                .map_err(|e| format!("invalid gzip: {e}"))?;
+        }
+        ContentEncoding::Deflate => {
+            flate2::read::ZlibDecoder::new(&compressed[..])
+                .read_to_end(&mut raw)
+                .map_err(|e| format!("invalid deflate: {e}"))?;
+        }
+        ContentEncoding::Brotli => {
+            brotli::Decompressor::new(&compressed[..], 4096)
+                .read_to_end(&mut raw)
+                .map_err(|e| format!("invalid brotli: {e}"))?;
+        }
+    }
+    let text = String::from_utf8(raw).map_err(|_| "decoded body is not UTF-8 text".to_string())?;
+
+    let mut decoded = response.clone();
+    decoded["body"] = serde_json::Value::String(text);
+    decoded["isBase64Encoded"] = serde_json::Value::Bool(false);
+    Ok(decoded.to_string())
+}
+
+/// Compress and base64-encode the body of a corrupted response again. Returns
+/// `None` if the strategy left no string body to encode (e.g. the response is
+/// no longer valid JSON), in which case it is forwarded as is.
+fn reencode_response_body(corrupted: &str, encoding: &ContentEncoding) -> Option<String> {
+    use base64::Engine;
+    use std::io::Write;
+
+    let mut response = serde_json::from_str::<serde_json::Value>(corrupted).ok()?;
+    let text = response.get("body")?.as_str()?.as_bytes().to_vec();
+    let compressed = match encoding {
+        ContentEncoding::Identity => text,
+        ContentEncoding::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&text).ok()?;
+            encoder.finish().ok()?
+        }
+        ContentEncoding::Deflate => {
+            let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&text).ok()?;
+            encoder.finish().ok()?
+        }
+        ContentEncoding::Brotli => {
+            let mut out = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                encoder.write_all(&text).ok()?;
+            }
+            out
+        }
+    };
+    response["body"] =
+        serde_json::Value::String(base64::engine::general_purpose::STANDARD.encode(compressed));
+    response["isBase64Encoded"] = serde_json::Value::Bool(true);
+    Some(response.to_string())
+}
+
+/// Break the encoding layer rather than the payload: a compressed body is cut
+/// short so decompression fails, and an uncompressed one gets an invalid
+/// base64 character.
+fn break_body_encoding(mut response: serde_json::Value, encoding: &ContentEncoding) -> String {
+    use base64::Engine;
+
+    info!(
+        source = "failure-lambda",
+        mode = "corruption",
+        action = "inject",
+        method = "break_encoding",
+        content_encoding = ?encoding,
+    );
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    let encoded = response["body"].as_str().unwrap_or_default().to_string();
+    let broken = match (encoding, engine.decode(&encoded)) {
+        (ContentEncoding::Identity, _) | (_, Err(_)) => {
+            let mut chars: Vec<char> = encoded.chars().collect();
+            let position = if chars.is_empty() {
+                0
+            } else {
+                rand::thread_rng().gen_range(0..chars.len())
+            };
+            chars.insert(position, '*');
+            chars.into_iter().collect()
+        }
+        (_, Ok(compressed)) => engine.encode(&compressed[..compressed.len() / 2]),
+    };
+    response["body"] = serde_json::Value::String(broken);
+    response.to_string()
+}
+
+/// Just over Lambda's 6 MB limit for synchronous invocation responses.
+const DEFAULT_RESPONSE_SIZE_BYTES: u64 = 6 * 1024 * 1024 + 1;
+const PADDING_HEADER_BYTES: usize = 8 * 1024;
+
+/// Inflate the response past `response_size_bytes` so Lambda rejects it with
+/// `Function.ResponseSizeTooLarge`. Pads the `body` field with whitespace, or
+/// with `oversize_target: "headers"` adds padding headers to an API Gateway
+/// style response. Non-object responses get trailing whitespace, which keeps
+/// JSON valid.
+fn oversize_body(flag: &FlagValue, body: &str) -> String {
+    let target_size = flag
+        .response_size_bytes
+        .unwrap_or(DEFAULT_RESPONSE_SIZE_BYTES) as usize;
+    let mut target = flag.oversize_target.clone().unwrap_or(OversizeTarget::Body);
+
+    info!(
+        source = "failure-lambda",
+        mode = "corruption",
+        action = "inject",
+        method = "oversize",
+        original_size_bytes = body.len(),
+        response_size_bytes = target_size,
+        target = ?target,
+    );
+
+    let mut json = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(json) => json,
+        Err(_) => {
+            let shortfall = (target_size + 1).saturating_sub(body.len());
+            return format!("{body}{}", " ".repeat(shortfall));
+        }
+    };
+
+    // The padded value is re-serialized compactly, which grows by exactly the
+    // number of padding spaces added, so the shortfall is measured against the
+    // compact form rather than the original (possibly pretty-printed) body
+    let compact = serde_json::to_string(&json).unwrap_or_else(|_| body.to_string());
+    let shortfall = (target_size + 1).saturating_sub(compact.len());
+    if shortfall == 0 {
+        return body.to_string();
+    }
+
+    // Padding a base64 body would make it undecodable, so pad headers instead
+    if json.get("isBase64Encoded") == Some(&serde_json::Value::Bool(true)) {
+        target = OversizeTarget::Headers;
+    }
+
+    match json {
+        serde_json::Value::Object(ref mut obj) => {
+            if target == OversizeTarget::Headers {
+                pad_headers(obj, shortfall);
+            } else {
+                // A missing or non-string body is replaced with padding
+                let mut padded = match obj.get("body") {
+                    Some(serde_json::Value::String(s)) => s.clone(),
+                    _ => String::new(),
+                };
+                padded.push_str(&" ".repeat(shortfall));
+                obj.insert("body".to_string(), serde_json::Value::String(padded));
+            }
+        }
+        serde_json::Value::String(ref mut s) => s.push_str(&" ".repeat(shortfall)),
+        _ => return format!("{compact}{}", " ".repeat(shortfall)),
+    }
+    serde_json::to_string(&json).unwrap_or_else(|_| body.to_string())
+}
+
+/// Add `x-failure-lambda-padding-N` headers totalling at least `min_bytes`.
+fn pad_headers(response: &mut serde_json::Map<String, serde_json::Value>, min_bytes: usize) {
+    let headers = response
+        .entry("headers")
+        .or_insert_with(|| serde_json::json!({}));
+    if !headers.is_object() {
+        *headers = serde_json::json!({});
+    }
+    let serde_json::Value::Object(headers) = headers else {
+        return;
+    };
+
+    let mut added = 0;
+    let mut index = 0;
+    while added < min_bytes {
+        let value = "x".repeat(PADDING_HEADER_BYTES);
+        added += value.len();
+        headers.insert(
+            format!("x-failure-lambda-padding-{index}"),
+            serde_json::Value::String(value),
+        );
+        index += 1;
+    }
+}
+
+fn mangle_string(input: &str) -> String {
+    if input.is_empty() {
+        return input.to_string();
+    }
+    let mut result = truncate_string(input);
+    result.push_str("\u{FFFD}\u{FFFD}\u{FFFD}");
+    result
+}
+
+/// Cut a string at a random point between 30% and 80% of its length.
+fn truncate_string(input: &str) -> String {
+    let truncate_fraction = 0.3 + rand::thread_rng().gen::<f64>() * 0.5;
+    let truncate_point = (input.len() as f64 * truncate_fraction).floor() as usize;
+    // Ensure we don't split a multi-byte character
+    let safe_point = input
+        .char_indices()
+        .take_while(|&(i, _)| i <= truncate_point)
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    input[..safe_point].to_string()
+}
+
+/// Flip one random low bit in each of `count` random ASCII characters. Only
+/// bits 0–6 are flipped so the result stays valid UTF-8.
+fn flip_bits(input: &str, count: usize) -> String {
+    let mut bytes = input.as_bytes().to_vec();
+    let ascii_positions: Vec<usize> = bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| b.is_ascii())
+        .map(|(i, _)| i)
+        .collect();
+    if ascii_positions.is_empty() {
+        return input.to_string();
+    }
+    let mut rng = rand::thread_rng();
+    for _ in 0..count {
+        let position = ascii_positions[rng.gen_range(0..ascii_positions.len())];
+        bytes[position] ^= 1 << rng.gen_range(0..7);
+    }
+    String::from_utf8(bytes).unwrap_or_else(|_| input.to_string())
+}
+
+const EXTRA_FIELD_NAME: &str = "failure_lambda_unexpected";
+
+/// Apply a field-level strategy to the response. Paths are dot-separated; a
+/// path under `body` descends into the `body` string when it holds JSON, which
+/// is re-encoded afterwards. Without `paths`, a default target is picked in the
+/// decoded body, or in the response itself when the body is not JSON.
+fn corrupt_fields(flag: &FlagValue, strategy: &CorruptionStrategy, body: &str) -> String {
+    info!(
+        source = "failure-lambda",
+        mode = "corruption",
+        action = "inject",
+        method = ?strategy,
+        paths = ?flag.paths,
+    );
+
+    let text_level = matches!(
+        strategy,
+        CorruptionStrategy::BitFlip | CorruptionStrategy::TruncateJson
+    );
+    let apply_text = |input: &str| match strategy {
+        CorruptionStrategy::BitFlip => flip_bits(input, 1),
+        _ => truncate_string(input),
+    };
+
+    let mut response = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(response) => response,
+        // Text-level strategies still apply to a non-JSON response
+        Err(_) if text_level => return apply_text(body),
+        Err(_) => {
+            warn!(
+                source = "failure-lambda",
+                mode = "corruption",
+                message = "response is not JSON; returning unchanged",
+            );
+            return body.to_string();
+        }
+    };
+
+    // Without paths, text-level strategies break the body string itself (or the
+    // whole response when there is none), producing invalid JSON
+    if text_level && flag.paths.is_none() {
+        return match response.get_mut("body") {
+            Some(serde_json::Value::String(body_str)) => {
+                *body_str = apply_text(body_str);
+                response.to_string()
+            }
+            _ => apply_text(body),
+        };
+    }
+
+    let mut decoded_body = response
+        .get("body")
+        .and_then(|b| b.as_str())
+        .and_then(|b| serde_json::from_str::<serde_json::Value>(b).ok())
+        .filter(|b| b.is_object() || b.is_array());
+
+    let paths = match flag.paths {
+        Some(ref paths) => paths.clone(),
+        None => default_corruption_paths(strategy, decoded_body.as_ref().unwrap_or(&response))
+            .into_iter()
+            .map(|path| match (&decoded_body, path.is_empty()) {
+                (Some(_), true) => "body".to_string(),
+                (Some(_), false) => format!("body.{path}"),
+                (None, _) => path,
+            })
+            .collect(),
+    };
+
+    for path in &paths {
+        let (document, subpath) = match (decoded_body.as_mut(), body_subpath(path)) {
+            (Some(decoded), Some(subpath)) => (decoded, subpath),
+            _ => (&mut response, path.as_str()),
+        };
+        if !corrupt_field(document, subpath, strategy, &apply_text) {
+            warn!(
+                source = "failure-lambda",
+                mode = "corruption",
+                path = %path,
+                message = "path not found or has an incompatible type; skipping",
+            );
+        }
+    }
+
+    if let Some(decoded) = decoded_body {
+        if let Some(obj) = response.as_object_mut() {
+            obj.insert("body".to_string(), serde_json::Value::String(decoded.to_string()));
+        }
+    }
+    serde_json::to_string(&response).unwrap_or_else(|_| body.to_string())
+}
+
+/// If `path` points into the response's `body`, return the remainder
+/// (empty for the body itself).
+fn body_subpath(path: &str) -> Option<&str> {
+    if path == "body" {
+        Some("")
+    } else {
+        path.strip_prefix("body.")
+    }
+}
+
+/// Pick targets when no `paths` are configured: a random top-level key for
+/// key-level strategies, every top-level array for `reorder_array`, and the
+/// document root otherwise.
+fn default_corruption_paths(strategy: &CorruptionStrategy, document: &serde_json::Value) -> Vec<String> {
+    match strategy {
+        CorruptionStrategy::TypeFlip | CorruptionStrategy::NullField | CorruptionStrategy::DropKey => {
+            let keys: Vec<&String> = document
+                .as_object()
+                .map(|obj| obj.keys().collect())
+                .unwrap_or_default();
+            if keys.is_empty() {
+                return Vec::new();
+            }
+            vec![keys[rand::thread_rng().gen_range(0..keys.len())].clone()]
+        }
+        CorruptionStrategy::ReorderArray => match document {
+            serde_json::Value::Object(obj) => obj
+                .iter()
+                .filter(|(_, v)| v.is_array())
+                .map(|(k, _)| k.clone())
+                .collect(),
+            _ => vec![String::new()],
+        },
+        _ => vec![String::new()],
+    }
+}
+
+/// Apply a field-level strategy at `path` within `document` (empty path for the
+/// root). Returns false if the path does not resolve to a compatible value.
+fn corrupt_field(
+    document: &mut serde_json::Value,
+    path: &str,
+    strategy: &CorruptionStrategy,
+    apply_text: &dyn Fn(&str) -> String,
+) -> bool {
+    let as_mutation = |operation| EventMutation {
+        path: path.to_string(),
+        operation,
+        value: None,
+        to: None,
+        length: None,
+        count: None,
+    };
+
+    match strategy {
+        CorruptionStrategy::TypeFlip if !path.is_empty() => {
+            apply_mutation(document, &as_mutation(MutationOperation::ChangeType))
+        }
+        // Only an existing field is nulled; `Set` would create missing ones
+        CorruptionStrategy::NullField if !path.is_empty() => match resolve_mut(document, path) {
+            Some(value) => {
+                *value = serde_json::Value::Null;
+                true
+            }
+            None => false,
+        },
+        CorruptionStrategy::DropKey if !path.is_empty() => {
+            apply_mutation(document, &as_mutation(MutationOperation::Delete))
+        }
+        CorruptionStrategy::ReorderArray => match resolve_mut(document, path) {
+            Some(serde_json::Value::Array(arr)) if arr.len() > 1 => {
+                let original = arr.clone();
+                arr.shuffle(&mut rand::thread_rng());
+                // A shuffle can land on the original order; rotate so the
+                // caller always sees a difference
+                if *arr == original {
+                    arr.rotate_left(1);
+                }
+                true
+            }
+            Some(serde_json::Value::Array(_)) => true,
+            _ => false,
+        },
+        CorruptionStrategy::ExtraField => match resolve_mut(document, path) {
+            Some(serde_json::Value::Object(obj)) => {
+                obj.insert(
+                    EXTRA_FIELD_NAME.to_string(),
+                    serde_json::json!({ "injected": true }),
+                );
+                true
+            }
+            _ => false,
+        },
+        CorruptionStrategy::BitFlip | CorruptionStrategy::TruncateJson => {
+            match resolve_mut(document, path) {
+                Some(serde_json::Value::String(s)) => {
+                    *s = apply_text(s);
+                    true
+                }
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+const DEFAULT_BATCH_FAILURE_PERCENTAGE: u32 = 50;
+
+/// Extract the item identifiers Lambda expects in `batchItemFailures` from a
+/// batch event: `messageId` for SQS, `kinesis.sequenceNumber` for Kinesis and
+/// `dynamodb.SequenceNumber` for DynamoDB streams. Order follows the event.
+fn batch_item_identifiers(event: &serde_json::Value) -> Vec<String> {
+    let records = match event.get("Records").and_then(|r| r.as_array()) {
+        Some(r) => r,
+        None => return Vec::new(),
+    };
+    records
+        .iter()
+        .filter_map(|record| {
+            record
+                .get("messageId")
+                .or_else(|| get_nested_value(record, "kinesis.sequenceNumber"))
+                .or_else(|| get_nested_value(record, "dynamodb.SequenceNumber"))
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        })
+        .collect()
+}
+
+/// Report a subset of the batch's records as failed. Picks
+/// `batch_failure_count` records (or `batch_failure_percentage` of them, rounded
+/// up) from the stored event and merges them into the response's
+/// `batchItemFailures`, keeping any failures the handler already reported.
+pub fn inject_batch_failures(flag: &FlagValue, event: &serde_json::Value, body: &str) -> String {
+    let identifiers = batch_item_identifiers(event);
+    if identifiers.is_empty() {
+        warn!(
+            source = "failure-lambda",
+            mode = "batchfailure",
+            message = "event has no SQS, Kinesis or DynamoDB records; returning unchanged",
+        );
+        return body.to_string();
+    }
+
+    let count = match flag.batch_failure_count {
+        Some(count) => count as usize,
+        None => {
+            let pct = flag
+                .batch_failure_percentage
+                .unwrap_or(DEFAULT_BATCH_FAILURE_PERCENTAGE)
+                .min(100) as usize;
+            (identifiers.len() * pct).div_ceil(100)
+        }
+    }
+    .min(identifiers.len());
+
+    // Sample indices rather than identifiers so the failures keep event order
+    let mut picked = rand::seq::index::sample(&mut rand::thread_rng(), identifiers.len(), count)
+        .into_vec();
+    picked.sort_unstable();
+
+    let mut response = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(obj)) => obj,
+        Ok(serde_json::Value::Null) => serde_json::Map::new(),
+        Err(_) if body.trim().is_empty() => serde_json::Map::new(),
+        _ => {
+            warn!(
+                source = "failure-lambda",
+                mode = "batchfailure",
+                message = "response is not a JSON object; replacing with {{ batchItemFailures }}",
+            );
+            serde_json::Map::new()
+        }
+    };
+
+    let mut failures = match response.remove("batchItemFailures") {
+        Some(serde_json::Value::Array(existing)) => existing,
+        _ => Vec::new(),
+    };
+    let already_reported: std::collections::HashSet<String> = failures
+        .iter()
+        .filter_map(|f| f.get("itemIdentifier").and_then(|v| v.as_str()))
+        .map(str::to_string)
+        .collect();
+
+    let mut injected = 0;
+    for index in picked {
+        let id = &identifiers[index];
+        if !already_reported.contains(id) {
+            failures.push(serde_json::json!({ "itemIdentifier": id }));
+            injected += 1;
+        }
+    }
+
+    info!(
+        source = "failure-lambda",
+        mode = "batchfailure",
+        action = "inject",
+        record_count = identifiers.len(),
+        injected_failures = injected,
+        total_failures = failures.len(),
+    );
+
+    response.insert("batchItemFailures".to_string(), serde_json::Value::Array(failures));
+    serde_json::Value::Object(response).to_string()
+}
+
+/// Per-invocation values substituted into message and body templates.
+pub struct TemplateContext<'a> {
+    pub request_id: &'a str,
+    pub function_name: String,
+    pub function_version: String,
+    pub deadline_ms: u64,
+    pub event: &'a serde_json::Value,
+}
+
+impl<'a> TemplateContext<'a> {
+    /// Build a context for the current invocation, reading the function's
+    /// name and version from the Lambda environment.
+    pub fn new(request_id: &'a str, deadline_ms: u64, event: &'a serde_json::Value) -> Self {
+        Self {
+            request_id,
+            function_name: std::env::var("AWS_LAMBDA_FUNCTION_NAME").unwrap_or_default(),
+            function_version: std::env::var("AWS_LAMBDA_FUNCTION_VERSION").unwrap_or_default(),
+            deadline_ms,
+            event,
+        }
+    }
+}
+
+/// Return a copy of `flag` with placeholders resolved in `exception_msg` and
+/// `body`, the only templated fields.
+pub fn render_flag_templates(flag: &FlagValue, mode: &str, ctx: &TemplateContext) -> FlagValue {
+    let mut rendered = flag.clone();
+    if let Some(ref msg) = flag.exception_msg {
+        rendered.exception_msg = Some(render_template(msg, mode, ctx));
+    }
+    if let Some(ref body) = flag.body {
+        rendered.body = Some(render_body_template(body, mode, ctx));
+    }
+    rendered
+}
+
+/// Substitute `{request_id}`, `{function_name}`, `{function_version}`,
+/// `{deadline_ms}`, `{mode}` and `{event:<path>}` in `template`. Event paths
+/// that don't resolve render as an empty string. Any other brace is kept as
+/// written, so JSON bodies pass through untouched.
+pub fn render_template(template: &str, mode: &str, ctx: &TemplateContext) -> String {
+    render_with(template, |name| resolve_placeholder(name, mode, ctx))
+}
+
+/// Like `render_template`, but when the body is a JSON template the
+/// substituted values are JSON-escaped, so quotes, backslashes and control
+/// characters in event values can't break the document. A body counts as
+/// JSON if it parses as an object or array with every placeholder replaced
+/// by `0`.
+fn render_body_template(template: &str, mode: &str, ctx: &TemplateContext) -> String {
+    let probe = render_with(template, |name| {
+        resolve_placeholder(name, mode, ctx).map(|_| "0".to_string())
+    });
+    let is_json = serde_json::from_str::<serde_json::Value>(&probe)
+        .is_ok_and(|value| value.is_object() || value.is_array());
+    if !is_json {
+        return render_template(template, mode, ctx);
+    }
+    render_with(template, |name| {
+        resolve_placeholder(name, mode, ctx).map(|value| {
+            let quoted = serde_json::Value::String(value).to_string();
+            quoted[1..quoted.len() - 1].to_string()
+        })
+    })
+}
+
+/// Replace each `{name}` that `resolve` knows, keeping any other brace.
+fn render_with(template: &str, resolve: impl Fn(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        output.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let replacement = after
+            .find('}')
+            .and_then(|close| resolve(&after[..close]).map(|r| (r, close)));
+        match replacement {
+            Some((value, close)) => {
+                output.push_str(&value);
+                rest = &after[close + 1..];
+            }
+            None => {
+                output.push('{');
+                rest = after;
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+fn resolve_placeholder(name: &str, mode: &str, ctx: &TemplateContext) -> Option<String> {
+    match name {
+        "request_id" => Some(ctx.request_id.to_string()),
+        "function_name" => Some(ctx.function_name.clone()),
+        "function_version" => Some(ctx.function_version.clone()),
+        "deadline_ms" => Some(ctx.deadline_ms.to_string()),
+        "mode" => Some(mode.to_string()),
+        _ => {
+            let path = name.strip_prefix("event:")?;
+            if path.is_empty() || path.contains(['{', '"']) {
+                return None;
+            }
+            Some(
+                get_nested_value(ctx.event, path)
+                    .map(json_value_to_string)
+                    .unwrap_or_default(),
+            )
+        }
+    }
+}
+
+/// Resolve a dot-separated path against a nested JSON value.
+pub fn get_nested_value<'a>(obj: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    let mut current = obj;
+    for part in path.split('.') {
+        match current.get(part) {
+            Some(v) => current = v,
+            None => return None,
+        }
+    }
+    Some(current)
+}
+
+/// Like `get_nested_value_mut`, but an empty path resolves to the root.
+fn resolve_mut<'a>(obj: &'a mut serde_json::Value, path: &str) -> Option<&'a mut serde_json::Value> {
+    if path.is_empty() {
+        Some(obj)
+    } else {
+        get_nested_value_mut(obj, path)
+    }
+}
+
+/// Mutable variant of `get_nested_value`. Numeric segments index into arrays,
+/// so `Records.0.body` resolves the first record's body.
+fn get_nested_value_mut<'a>(
+    obj: &'a mut serde_json::Value,
+    path: &str,
+) -> Option<&'a mut serde_json::Value> {
+    let mut current = obj;
+    for part in path.split('.') {
+        current = match current {
+            serde_json::Value::Object(map) => map.get_mut(part)?,
+            serde_json::Value::Array(arr) => arr.get_mut(part.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+// Cache for compiled regex patterns used in match conditions.
+thread_local! {
+    static REGEX_CACHE: std::cell::RefCell<HashMap<String, Regex>> =
+        std::cell::RefCell::new(HashMap::new());
+}
+
+fn get_cached_regex(pattern: &str) -> Option<Regex> {
+    REGEX_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if let Some(re) = cache.get(pattern) {
+            return Some(re.clone());
+        }
+        match Regex::new(pattern) {
+            Ok(re) => {
+                cache.insert(pattern.to_string(), re.clone());
+                Some(re)
+            }
+            Err(_) => None,
+        }
+    })
+}
+
+/// Evaluate a single match operator against an actual JSON value.
+fn match_operator(actual: Option<&serde_json::Value>, operator: &MatchOperator, value: Option<&str>) -> bool {
+    match operator {
+        MatchOperator::Exists => actual.is_some() && !actual.unwrap().is_null(),
+        MatchOperator::StartsWith => {
+            match actual {
+                Some(v) if !v.is_null() => {
+                    let actual_str = json_value_to_string(v);
+                    actual_str.starts_with(value.unwrap_or(""))
+                }
+                _ => false,
+            }
+        }
+        MatchOperator::Regex => {
+            match actual {
+                Some(v) if !v.is_null() => {
+                    let actual_str = json_value_to_string(v);
+                    match get_cached_regex(value.unwrap_or("")) {
+                        Some(re) => re.is_match(&actual_str),
+                        None => false,
+                    }
+                }
+                _ => false,
+            }
+        }
+        MatchOperator::Eq => {
+            match actual {
+                Some(v) if !v.is_null() => {
+                    let actual_str = json_value_to_string(v);
+                    actual_str == value.unwrap_or("")
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+fn json_value_to_string(v: &serde_json::Value) -> String {
+    match v {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Number(n) => n.to_string(),
         serde_json::Value::Bool(b) => b.to_string(),
         other => other.to_string(),
     }
-}
+}
+
+/// Check whether all match conditions are satisfied by the event.
+pub fn matches_conditions(event: &serde_json::Value, conditions: &[MatchCondition]) -> bool {
+    conditions.iter().all(|condition| {
+        let actual = get_nested_value(event, &condition.path);
+        let operator = condition.operator.as_ref().cloned().unwrap_or(MatchOperator::Eq);
+        match_operator(actual, &operator, condition.value.as_deref())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DenyEntry, DnsDelay};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_split_latency() {
+        assert_eq!(split_latency(100, &Phase::Pre), (100, 0));
+        assert_eq!(split_latency(100, &Phase::Post), (0, 100));
+        assert_eq!(split_latency(101, &Phase::Split), (51, 50));
+    }
+
+    #[tokio::test]
+    async fn test_inject_latency_post_phase_returns_delay() {
+        let flag = FlagValue {
+            enabled: true,
+            min_latency: Some(20.0),
+            max_latency: Some(20.0),
+            phase: Some(Phase::Post),
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        let post_ms = inject_latency(&flag).await;
+        assert_eq!(post_ms, 20);
+        assert!(start.elapsed() < std::time::Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_timeout_sleep_ms() {
+        let mut flag = FlagValue {
+            enabled: true,
+            timeout_buffer_ms: Some(100.0),
+            ..Default::default()
+        };
+        assert_eq!(timeout_sleep_ms(10_000, 9_000, &flag), Some(900));
+        assert_eq!(timeout_sleep_ms(10_000, 9_950, &flag), Some(0));
+
+        flag.timeout_mode = Some(TimeoutMode::Fraction);
+        flag.timeout_percentage = Some(25.0);
+        assert_eq!(timeout_sleep_ms(10_000, 6_000, &flag), Some(1_000));
+        assert_eq!(timeout_sleep_ms(10_000, 12_000, &flag), Some(0));
+
+        flag.timeout_mode = Some(TimeoutMode::Hang);
+        assert_eq!(timeout_sleep_ms(10_000, 6_000, &flag), None);
+
+        flag.timeout_mode = Some(TimeoutMode::Response);
+        assert_eq!(timeout_sleep_ms(10_000, 6_000, &flag), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_inject_response_timeout_past_deadline() {
+        let start = std::time::Instant::now();
+        inject_response_timeout(now_ms().saturating_sub(RESPONSE_TIMEOUT_OVERSHOOT_MS + 1_000)).await;
+        assert!(start.elapsed() < std::time::Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_denylist_rules() {
+        let flag = FlagValue {
+            enabled: true,
+            deny_list: Some(vec![
+                DenyEntry::Pattern("s3\\.amazonaws\\.com".to_string()),
+                DenyEntry::Rule(DenyRule {
+                    pattern: "dynamodb".to_string(),
+                    result: Some(DnsResult::Again),
+                    percentage: Some(25),
+                    ..Default::default()
+                }),
+                DenyEntry::Rule(DenyRule {
+                    pattern: "sqs".to_string(),
+                    result: Some(DnsResult::System),
+                    ..Default::default()
+                }),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            denylist_rules(&flag, "127.0.0.1:9001"),
+            vec![
+                "deny\tresult=EAI_NONAME\ts3\\.amazonaws\\.com".to_string(),
+                "deny\tresult=EAI_AGAIN\tpercentage=25\tdynamodb".to_string(),
+                "deny\tresult=EAI_SYSTEM\terrno=5\tsqs".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_denylist_rules_allow_list() {
+        let flag = FlagValue {
+            enabled: true,
+            allow_list: Some(vec!["\\.example\\.com$".to_string()]),
+            deny_list: Some(vec![DenyEntry::Rule(DenyRule {
+                pattern: "dynamodb".to_string(),
+                result: Some(DnsResult::Again),
+                ..Default::default()
+            })]),
+            ..Default::default()
+        };
+        let rules = denylist_rules(&flag, "169.254.100.1:9001");
+        assert_eq!(rules[0], "allow\t\\.example\\.com$");
+        assert!(rules.contains(&"allow\t^localhost$".to_string()));
+        assert!(rules.contains(&"allow\t^169\\.254\\.100\\.1$".to_string()));
+        assert_eq!(rules[rules.len() - 2], "deny\tresult=EAI_AGAIN\tdynamodb");
+        assert_eq!(rules[rules.len() - 1], "deny\tresult=EAI_NONAME\t.");
+
+        let ipv4 = Regex::new(IPV4_LITERAL_PATTERN).unwrap();
+        assert!(ipv4.is_match("10.0.0.1"));
+        assert!(!ipv4.is_match("s3.amazonaws.com"));
+    }
 
-/// Check whether all match conditions are satisfied by the event.
-pub fn matches_conditions(event: &serde_json::Value, conditions: &[MatchCondition]) -> bool {
-    conditions.iter().all(|condition| {
-        let actual = get_nested_value(event, &condition.path);
-        let operator = condition.operator.as_ref().cloned().unwrap_or(MatchOperator::Eq);
-        match_operator(actual, &operator, condition.value.as_deref())
-    })
-}
+    #[test]
+    fn test_denylist_rewrite_rules() {
+        let flag = FlagValue {
+            enabled: true,
+            deny_list: Some(vec![
+                DenyEntry::Rule(DenyRule {
+                    pattern: "s3".to_string(),
+                    result: Some(DnsResult::Blackhole),
+                    ..Default::default()
+                }),
+                DenyEntry::Rule(DenyRule {
+                    pattern: "dynamodb".to_string(),
+                    result: Some(DnsResult::Blackhole),
+                    family: Some(AddressFamily::Ipv6),
+                    ..Default::default()
+                }),
+                DenyEntry::Rule(DenyRule {
+                    pattern: "sqs".to_string(),
+                    addresses: Some(vec!["127.0.0.1".to_string(), "::1".to_string()]),
+                    family: Some(AddressFamily::Ipv4),
+                    ..Default::default()
+                }),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            denylist_rules(&flag, "127.0.0.1:9001"),
+            vec![
+                "rewrite\taddress=192.0.2.1\taddress=2001:db8::1\ts3".to_string(),
+                "rewrite\taddress=2001:db8::1\tdynamodb".to_string(),
+                "rewrite\taddress=127.0.0.1\tsqs".to_string(),
+            ]
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_denylist_longest_rule_fits_intercept_line() {
+        // The intercept library reads lines of at most 511 bytes; config
+        // validation caps patterns at 256 bytes and rewrites at 4 addresses
+        let address = "ffff:ffff:ffff:ffff:ffff:ffff:255.255.255.255".to_string();
+        let flag = FlagValue {
+            enabled: true,
+            deny_list: Some(vec![DenyEntry::Rule(DenyRule {
+                pattern: "a".repeat(256),
+                addresses: Some(vec![address; 4]),
+                percentage: Some(99),
+                ..Default::default()
+            })]),
+            ..Default::default()
+        };
+        let rules = denylist_rules(&flag, "127.0.0.1:9001");
+        assert!(rules[0].len() < 511, "{} bytes", rules[0].len());
+    }
+
+    #[test]
+    fn test_dns_latency_rules() {
+        let flag = FlagValue {
+            enabled: true,
+            dns_delays: Some(vec![
+                DnsDelay { pattern: "\\.amazonaws\\.com$".to_string(), min_latency: 500.0, max_latency: Some(2000.0) },
+                DnsDelay { pattern: "example".to_string(), min_latency: 100.5, max_latency: None },
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            dns_latency_rules(&flag),
+            vec![
+                "delay\tmin=500\tmax=2000\t\\.amazonaws\\.com$".to_string(),
+                "delay\tmin=100\tmax=100\texample".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_connection_rules() {
+        let flag = FlagValue {
+            enabled: true,
+            connect_rules: Some(vec![
+                ConnectRule {
+                    cidr: "10.0.0.0/8".to_string(),
+                    ports: Some(vec![443, 8443]),
+                    action: ConnectAction::Refuse,
+                    min_latency: None,
+                    max_latency: None,
+                    percentage: Some(25),
+                },
+                ConnectRule {
+                    cidr: "2001:db8::/32".to_string(),
+                    ports: None,
+                    action: ConnectAction::Delay,
+                    min_latency: Some(250.0),
+                    max_latency: None,
+                    percentage: Some(100),
+                },
+                ConnectRule {
+                    cidr: "172.16.0.0/12".to_string(),
+                    ports: None,
+                    action: ConnectAction::Timeout,
+                    min_latency: None,
+                    max_latency: None,
+                    percentage: None,
+                },
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            connection_rules(&flag),
+            vec![
+                "refuse\tports=443,8443\tpercentage=25\t10.0.0.0/8".to_string(),
+                "delay\tmin=250\tmax=250\t2001:db8::/32".to_string(),
+                "timeout\t172.16.0.0/12".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inject_cpu_stops_on_drop() {
+        let flag = FlagValue {
+            enabled: true,
+            cpu_threads: Some(2),
+            cpu_duty_cycle: Some(50),
+            ..Default::default()
+        };
+        let load = inject_cpu(&flag);
+        let stop = Arc::clone(&load.stop);
+        let periods = Arc::clone(&load.periods);
+        assert_eq!(load.threads.len(), 2);
+        thread::sleep(CPU_PERIOD * 5);
+        assert!(periods.load(Ordering::Relaxed) > 0, "threads never spun");
+        assert!(load.threads.iter().all(|h| !h.is_finished()));
+
+        drop(load);
+        assert!(stop.load(Ordering::Relaxed));
+        // Each thread held a clone of `stop`; joined threads have released theirs
+        assert_eq!(Arc::strong_count(&stop), 1);
+        let after_drop = periods.load(Ordering::Relaxed);
+        thread::sleep(CPU_PERIOD * 3);
+        assert_eq!(periods.load(Ordering::Relaxed), after_drop);
+    }
+
+    #[tokio::test]
+    async fn test_inject_cpu_joins_off_runtime() {
+        let flag = FlagValue {
+            enabled: true,
+            cpu_threads: Some(2),
+            ..Default::default()
+        };
+        let load = inject_cpu(&flag);
+        let stop = Arc::clone(&load.stop);
+        tokio::time::sleep(CPU_PERIOD * 2).await;
+
+        // Inside the runtime, drop only signals the threads; a blocking task
+        // joins them
+        drop(load);
+        assert!(stop.load(Ordering::Relaxed));
+        let joined = async {
+            while Arc::strong_count(&stop) > 1 {
+                tokio::time::sleep(CPU_PERIOD).await;
+            }
+        };
+        assert!(tokio::time::timeout(Duration::from_secs(2), joined).await.is_ok());
+    }
+
+    #[test]
+    fn test_memory_target_mb() {
+        let mut flag = FlagValue {
+            enabled: true,
+            memory_percentage: Some(50),
+            ..Default::default()
+        };
+        assert_eq!(memory_target_mb(&flag, Some(1024)), Some(512));
+        assert_eq!(memory_target_mb(&flag, None), None);
+        flag.memory_mb = Some(64);
+        assert_eq!(memory_target_mb(&flag, Some(1024)), Some(64));
+    }
+
+    #[test]
+    fn test_inject_memory_held_for_invocation() {
+        let flag = FlagValue {
+            enabled: true,
+            memory_mb: Some(2),
+            ..Default::default()
+        };
+        let mut leaked = Vec::new();
+        let allocation = inject_memory(&flag, &mut leaked).unwrap();
+        assert_eq!(allocation.len(), 2 * MB);
+        assert!(leaked.is_empty());
+    }
+
+    #[test]
+    fn test_inject_memory_leak_grows_until_cap() {
+        let flag = FlagValue {
+            enabled: true,
+            memory_mb: Some(2),
+            memory_leak: Some(true),
+            memory_leak_max_mb: Some(5),
+            ..Default::default()
+        };
+        let mut leaked = Vec::new();
+        for _ in 0..4 {
+            assert!(inject_memory(&flag, &mut leaked).is_none());
+        }
+        assert_eq!(held_mb(&leaked), 5);
+        assert_eq!(leaked.len(), 3);
+    }
+
+    fn latency_samples(flag: &FlagValue) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(42);
+        (0..10_000).map(|_| sample_latency(flag, &mut rng)).collect()
+    }
+
+    fn mean(samples: &[f64]) -> f64 {
+        samples.iter().sum::<f64>() / samples.len() as f64
+    }
+
+    fn percentile(samples: &[f64], p: f64) -> f64 {
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        sorted[((sorted.len() - 1) as f64 * p / 100.0).round() as usize]
+    }
+
+    #[test]
+    fn test_sample_latency_uniform() {
+        let flag = FlagValue {
+            min_latency: Some(100.0),
+            max_latency: Some(200.0),
+            ..Default::default()
+        };
+        let samples = latency_samples(&flag);
+        assert!(samples.iter().all(|s| (100.0..=200.0).contains(s)));
+        assert!((mean(&samples) - 150.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn test_sample_latency_normal() {
+        let flag = FlagValue {
+            distribution: Some(LatencyDistribution::Normal),
+            mean_latency: Some(500.0),
+            stddev_latency: Some(50.0),
+            ..Default::default()
+        };
+        let samples = latency_samples(&flag);
+        assert!((mean(&samples) - 500.0).abs() < 2.0);
+        // ~68% of samples fall within one standard deviation
+        let within = samples.iter().filter(|s| (450.0..=550.0).contains(*s)).count();
+        assert!((6_600..7_000).contains(&within), "{within}");
+    }
+
+    #[test]
+    fn test_sample_latency_normal_clamped() {
+        let flag = FlagValue {
+            distribution: Some(LatencyDistribution::Normal),
+            mean_latency: Some(500.0),
+            stddev_latency: Some(200.0),
+            min_latency: Some(400.0),
+            max_latency: Some(600.0),
+            ..Default::default()
+        };
+        let samples = latency_samples(&flag);
+        assert!(samples.iter().all(|s| (400.0..=600.0).contains(s)));
+        assert!(samples.iter().filter(|s| **s == 400.0).count() > 2_500);
+    }
+
+    #[test]
+    fn test_sample_latency_exponential() {
+        let flag = FlagValue {
+            distribution: Some(LatencyDistribution::Exponential),
+            mean_latency: Some(100.0),
+            ..Default::default()
+        };
+        let samples = latency_samples(&flag);
+        assert!((mean(&samples) - 100.0).abs() < 4.0);
+        // Median of an exponential is mean * ln 2
+        assert!((percentile(&samples, 50.0) - 69.3).abs() < 4.0);
+    }
+
+    #[test]
+    fn test_sample_latency_log_normal() {
+        let flag = FlagValue {
+            distribution: Some(LatencyDistribution::LogNormal),
+            mean_latency: Some(200.0),
+            stddev_latency: Some(100.0),
+            ..Default::default()
+        };
+        let samples = latency_samples(&flag);
+        assert!((mean(&samples) - 200.0).abs() < 5.0);
+        // Right-skewed: the median sits below the mean
+        assert!(percentile(&samples, 50.0) < 190.0);
+    }
+
+    #[test]
+    fn test_sample_latency_pareto() {
+        let flag = FlagValue {
+            distribution: Some(LatencyDistribution::Pareto),
+            min_latency: Some(50.0),
+            max_latency: Some(5_000.0),
+            pareto_shape: Some(2.0),
+            ..Default::default()
+        };
+        let samples = latency_samples(&flag);
+        assert!(samples.iter().all(|s| (50.0..=5_000.0).contains(s)));
+        // Median of a Pareto is scale * 2^(1/shape)
+        assert!((percentile(&samples, 50.0) - 70.7).abs() < 3.0);
+        assert!(percentile(&samples, 99.0) > 400.0);
+    }
+
+    #[test]
+    fn test_sample_latency_empirical() {
+        let flag = FlagValue {
+            distribution: Some(LatencyDistribution::Empirical),
+            min_latency: Some(10.0),
+            max_latency: Some(2_000.0),
+            percentiles: Some(vec![
+                LatencyPercentile { percentile: 99.0, latency: 1_000.0 },
+                LatencyPercentile { percentile: 50.0, latency: 100.0 },
+                LatencyPercentile { percentile: 90.0, latency: 300.0 },
+            ]),
+            ..Default::default()
+        };
+        let samples = latency_samples(&flag);
+        assert!(samples.iter().all(|s| (10.0..=2_000.0).contains(s)));
+        assert!((percentile(&samples, 50.0) - 100.0).abs() < 5.0);
+        assert!((percentile(&samples, 90.0) - 300.0).abs() < 15.0);
+        assert!((percentile(&samples, 99.0) - 1_000.0).abs() < 100.0);
+    }
 
     #[test]
     fn test_get_nested_value() {
@@ -483,48 +2633,353 @@ mod tests {
         assert!(matches_conditions(&event, &[]));
     }
 
+    fn mutation_flag(mutations: Vec<EventMutation>) -> FlagValue {
+        FlagValue {
+            enabled: true,
+            mutations: Some(mutations),
+            ..Default::default()
+        }
+    }
+
+    fn mutation(path: &str, operation: MutationOperation) -> EventMutation {
+        EventMutation {
+            path: path.to_string(),
+            operation,
+            value: None,
+            to: None,
+            length: None,
+            count: None,
+        }
+    }
+
+    #[test]
+    fn test_mutate_event_delete_and_set() {
+        let event = serde_json::json!({
+            "headers": { "authorization": "Bearer x", "host": "example.com" },
+            "body": "{}"
+        });
+        let mut set_value = mutation("headers.host", MutationOperation::Set);
+        set_value.value = Some(serde_json::json!(42));
+        let flag = mutation_flag(vec![
+            mutation("headers.authorization", MutationOperation::Delete),
+            mutation("body", MutationOperation::Set),
+            set_value,
+        ]);
+        let mutated = mutate_event(&flag, &event).unwrap();
+        assert!(mutated["headers"].get("authorization").is_none());
+        assert!(mutated["body"].is_null());
+        assert_eq!(mutated["headers"]["host"], 42);
+        // The original event is left untouched for match evaluation
+        assert_eq!(event["headers"]["authorization"], "Bearer x");
+    }
+
+    #[test]
+    fn test_mutate_event_change_type() {
+        let event = serde_json::json!({ "limit": "25", "ratio": "2.5", "count": 3, "flag": true });
+        let mut to_bool = mutation("count", MutationOperation::ChangeType);
+        to_bool.to = Some(JsonType::Boolean);
+        let flag = mutation_flag(vec![
+            mutation("limit", MutationOperation::ChangeType),
+            mutation("ratio", MutationOperation::ChangeType),
+            to_bool,
+            mutation("flag", MutationOperation::ChangeType),
+        ]);
+        let mutated = mutate_event(&flag, &event).unwrap();
+        assert!(mutated["limit"].is_i64());
+        assert_eq!(mutated["limit"], 25);
+        assert_eq!(mutated["ratio"], 2.5);
+        assert_eq!(mutated["count"], true);
+        assert_eq!(mutated["flag"], "true");
+    }
+
+    #[test]
+    fn test_mutate_event_truncate() {
+        let event = serde_json::json!({ "name": "abcdef", "items": [1, 2, 3, 4] });
+        let mut truncate_name = mutation("name", MutationOperation::Truncate);
+        truncate_name.length = Some(2);
+        let flag = mutation_flag(vec![
+            truncate_name,
+            mutation("items", MutationOperation::Truncate),
+        ]);
+        let mutated = mutate_event(&flag, &event).unwrap();
+        assert_eq!(mutated["name"], "ab");
+        assert_eq!(mutated["items"], serde_json::json!([1, 2]));
+    }
+
+    #[test]
+    fn test_mutate_event_drop_elements() {
+        let event = serde_json::json!({ "Records": [{ "id": 1 }, { "id": 2 }, { "id": 3 }] });
+        let mut drop = mutation("Records", MutationOperation::DropElements);
+        drop.count = Some(2);
+        let mutated = mutate_event(&mutation_flag(vec![drop]), &event).unwrap();
+        assert_eq!(mutated["Records"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_mutate_event_array_index_path() {
+        let event = serde_json::json!({ "Records": [{ "body": "a" }, { "body": "b" }] });
+        let flag = mutation_flag(vec![mutation("Records.1.body", MutationOperation::Delete)]);
+        let mutated = mutate_event(&flag, &event).unwrap();
+        assert_eq!(mutated["Records"][0]["body"], "a");
+        assert!(mutated["Records"][1].get("body").is_none());
+    }
+
+    #[test]
+    fn test_mutate_event_missing_path_and_incompatible_type() {
+        let event = serde_json::json!({ "count": 3, "items": [1] });
+        let flag = mutation_flag(vec![
+            mutation("missing.path", MutationOperation::Truncate),
+            mutation("count", MutationOperation::Truncate),
+            mutation("count.nested", MutationOperation::Set),
+            mutation("items.5", MutationOperation::Set),
+        ]);
+        let mutated = mutate_event(&flag, &event).unwrap();
+        assert_eq!(mutated, event);
+    }
+
+    #[test]
+    fn test_mutate_event_set_creates_missing_parents() {
+        let event = serde_json::json!({ "Records": [{}] });
+        let mut nested = mutation("requestContext.authorizer.claims", MutationOperation::Set);
+        nested.value = Some(serde_json::json!({ "sub": "x" }));
+        let flag = mutation_flag(vec![nested, mutation("Records.0.body.raw", MutationOperation::Set)]);
+        let mutated = mutate_event(&flag, &event).unwrap();
+        assert_eq!(mutated["requestContext"]["authorizer"]["claims"]["sub"], "x");
+        assert!(mutated["Records"][0]["body"]["raw"].is_null());
+        assert!(mutated["Records"][0]["body"].get("raw").is_some());
+    }
+
+    #[test]
+    fn test_mutate_event_non_object_event() {
+        let flag = mutation_flag(vec![mutation("body", MutationOperation::Delete)]);
+        assert!(mutate_event(&flag, &serde_json::json!("raw string")).is_none());
+    }
+
+    #[test]
+    fn test_build_exception_payload() {
+        let flag = FlagValue {
+            enabled: true,
+            exception_msg: Some("chaos test".to_string()),
+            ..Default::default()
+        };
+        let payload = build_exception_payload(&flag);
+        assert_eq!(payload["errorMessage"], "chaos test");
+        assert_eq!(payload["errorType"], "FailureLambdaException");
+    }
+
+    #[test]
+    fn test_build_exception_payload_default_msg() {
+        let flag = FlagValue {
+            enabled: true,
+            ..Default::default()
+        };
+        let payload = build_exception_payload(&flag);
+        assert_eq!(payload["errorMessage"], "Injected exception");
+    }
+
+    #[test]
+    fn test_build_exception_payload_custom_error_type() {
+        let flag = FlagValue {
+            enabled: true,
+            error_type: Some("Runtime.DownstreamError".to_string()),
+            ..Default::default()
+        };
+        let payload = build_exception_payload(&flag);
+        assert_eq!(payload["errorType"], "Runtime.DownstreamError");
+        assert_eq!(exception_error_type(&flag), "Runtime.DownstreamError");
+    }
+
+    #[test]
+    fn test_build_exception_payload_runtime_presets() {
+        let flag = FlagValue {
+            enabled: true,
+            exception_msg: Some("boom".to_string()),
+            runtime: Some(ErrorRuntime::Node),
+            ..Default::default()
+        };
+        let payload = build_exception_payload(&flag);
+        assert_eq!(payload["errorType"], "Error");
+        assert_eq!(payload["trace"][0], "Error: boom");
+        assert!(payload.get("stackTrace").is_none());
+
+        let flag = FlagValue {
+            runtime: Some(ErrorRuntime::Python),
+            ..flag
+        };
+        let payload = build_exception_payload(&flag);
+        assert_eq!(payload["errorType"], "Exception");
+        assert!(payload["stackTrace"][0].as_str().unwrap().contains("lambda_handler"));
+
+        let flag = FlagValue {
+            runtime: Some(ErrorRuntime::Java),
+            error_type: Some("com.example.DownstreamException".to_string()),
+            ..flag
+        };
+        let payload = build_exception_payload(&flag);
+        assert_eq!(payload["errorType"], "com.example.DownstreamException");
+        assert_eq!(payload["stackTrace"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_build_exception_payload_custom_stack_trace() {
+        let flag = FlagValue {
+            enabled: true,
+            stack_trace: Some(vec!["at handler (index.js:1:1)".to_string()]),
+            ..Default::default()
+        };
+        let payload = build_exception_payload(&flag);
+        assert_eq!(payload["stackTrace"], serde_json::json!(["at handler (index.js:1:1)"]));
+    }
+
+    #[test]
+    fn test_build_xray_error_cause() {
+        let payload = serde_json::json!({
+            "errorMessage": "délai dépassé",
+            "errorType": "TimeoutError",
+            "stackTrace": ["  at handler (index.js:1:1)"]
+        });
+        let header = build_xray_error_cause(&payload);
+        assert!(header.is_ascii());
+        let cause: serde_json::Value = serde_json::from_str(&header).unwrap();
+        assert_eq!(cause["exceptions"][0]["type"], "TimeoutError");
+        assert_eq!(cause["exceptions"][0]["message"], "délai dépassé");
+        assert_eq!(cause["exceptions"][0]["stack"][0]["label"], "at handler (index.js:1:1)");
+    }
+
+    /// The response payload of a statuscode injection that isn't an error.
+    fn statuscode_response(flag: &FlagValue, event: &serde_json::Value) -> serde_json::Value {
+        match build_statuscode_injection(flag, event) {
+            StatusCodeInjection::Response(payload) => payload,
+            StatusCodeInjection::Error(_) => panic!("expected a response payload"),
+        }
+    }
+
+    #[test]
+    fn test_build_statuscode_payload() {
+        let flag = FlagValue {
+            enabled: true,
+            status_code: Some(503),
+            ..Default::default()
+        };
+        let payload = statuscode_response(&flag, &serde_json::json!({}));
+        assert_eq!(payload["statusCode"], 503);
+        assert!(payload["headers"]["Content-Type"].as_str().unwrap().contains("json"));
+    }
+
+    #[test]
+    fn test_build_statuscode_payload_default() {
+        let flag = FlagValue {
+            enabled: true,
+            ..Default::default()
+        };
+        let payload = statuscode_response(&flag, &serde_json::json!({}));
+        assert_eq!(payload["statusCode"], 500);
+    }
+
+    #[test]
+    fn test_build_statuscode_payload_custom_body() {
+        let flag = FlagValue {
+            enabled: true,
+            status_code: Some(502),
+            body: Some("Bad Gateway".to_string()),
+            ..Default::default()
+        };
+        let payload = statuscode_response(&flag, &serde_json::json!({}));
+        assert_eq!(payload["statusCode"], 502);
+        assert_eq!(payload["body"], "Bad Gateway");
+    }
+
+    #[test]
+    fn test_detect_response_format() {
+        let cases = [
+            (serde_json::json!({ "httpMethod": "GET", "requestContext": { "resourceId": "x" } }), ResponseFormat::ApiGatewayV1),
+            (serde_json::json!({ "version": "2.0", "requestContext": { "domainName": "abc.execute-api.eu-west-1.amazonaws.com" } }), ResponseFormat::ApiGatewayV2),
+            (serde_json::json!({ "version": "2.0", "requestContext": { "domainName": "abc.lambda-url.eu-west-1.on.aws" } }), ResponseFormat::FunctionUrl),
+            (serde_json::json!({ "requestContext": { "elb": { "targetGroupArn": "arn" } } }), ResponseFormat::Alb),
+            (serde_json::json!({ "Records": [{ "cf": { "request": {} } }] }), ResponseFormat::CloudFront),
+            (serde_json::json!({ "arguments": {}, "info": { "fieldName": "getItem" } }), ResponseFormat::AppSync),
+            (serde_json::json!({ "custom": "payload" }), ResponseFormat::ApiGatewayV1),
+        ];
+        for (event, expected) in cases {
+            assert_eq!(detect_response_format(&event), expected, "{event}");
+        }
+    }
+
     #[test]
-    fn test_build_exception_payload() {
+    fn test_build_statuscode_payload_alb() {
         let flag = FlagValue {
             enabled: true,
-            exception_msg: Some("chaos test".to_string()),
+            status_code: Some(503),
             ..Default::default()
         };
-        let payload = build_exception_payload(&flag);
-        assert_eq!(payload["errorMessage"], "chaos test");
-        assert_eq!(payload["errorType"], "FailureLambdaException");
+        let event = serde_json::json!({ "requestContext": { "elb": {} }, "headers": {} });
+        let payload = statuscode_response(&flag, &event);
+        assert_eq!(payload["statusDescription"], "503 Service Unavailable");
+        assert_eq!(payload["headers"]["Content-Type"], "application/json");
+        assert!(payload.get("multiValueHeaders").is_none());
+
+        let event = serde_json::json!({ "requestContext": { "elb": {} }, "multiValueHeaders": {} });
+        let payload = statuscode_response(&flag, &event);
+        assert_eq!(payload["multiValueHeaders"]["Content-Type"][0], "application/json");
+        assert!(payload.get("headers").is_none());
     }
 
     #[test]
-    fn test_build_exception_payload_default_msg() {
+    fn test_build_statuscode_payload_cloudfront() {
         let flag = FlagValue {
             enabled: true,
+            status_code: Some(502),
             ..Default::default()
         };
-        let payload = build_exception_payload(&flag);
-        assert_eq!(payload["errorMessage"], "Injected exception");
+        let event = serde_json::json!({ "Records": [{ "cf": {} }] });
+        let payload = statuscode_response(&flag, &event);
+        assert_eq!(payload["status"], "502");
+        assert_eq!(payload["statusDescription"], "Bad Gateway");
+        assert_eq!(payload["headers"]["content-type"][0]["key"], "Content-Type");
+        assert_eq!(payload["headers"]["content-type"][0]["value"], "application/json");
     }
 
     #[test]
-    fn test_build_statuscode_payload() {
+    fn test_build_statuscode_payload_appsync() {
         let flag = FlagValue {
             enabled: true,
-            status_code: Some(503),
+            status_code: Some(429),
+            body: Some("slow down".to_string()),
             ..Default::default()
         };
-        let payload = build_statuscode_payload(&flag);
-        assert_eq!(payload["statusCode"], 503);
-        assert!(payload["headers"]["Content-Type"].as_str().unwrap().contains("json"));
+        let event = serde_json::json!({ "arguments": {}, "info": { "fieldName": "getItem" } });
+        let StatusCodeInjection::Error(error) = build_statuscode_injection(&flag, &event) else {
+            panic!("expected an error for AppSync");
+        };
+        let payload = build_exception_payload(&error);
+        assert_eq!(payload["errorMessage"], "slow down");
+        assert_eq!(payload["errorType"], "HTTP429");
     }
 
     #[test]
-    fn test_build_statuscode_payload_default() {
+    fn test_build_statuscode_payload_v2_custom_headers_and_override() {
         let flag = FlagValue {
             enabled: true,
+            status_code: Some(503),
+            body: Some("<h1>down</h1>".to_string()),
+            content_type: Some("text/html".to_string()),
+            headers: Some(HashMap::from([
+                ("Retry-After".to_string(), "30".to_string()),
+                ("content-type".to_string(), "text/plain".to_string()),
+            ])),
+            response_format: Some(ResponseFormat::FunctionUrl),
             ..Default::default()
         };
-        let payload = build_statuscode_payload(&flag);
-        assert_eq!(payload["statusCode"], 500);
+        // The override wins over the detected (ALB) format
+        let event = serde_json::json!({ "requestContext": { "elb": {} } });
+        let payload = statuscode_response(&flag, &event);
+        assert_eq!(payload["isBase64Encoded"], false);
+        assert!(payload.get("statusDescription").is_none());
+        assert_eq!(payload["headers"]["Retry-After"], "30");
+        // Custom headers replace the default Content-Type case-insensitively
+        assert_eq!(payload["headers"]["content-type"], "text/plain");
+        assert!(payload["headers"].get("Content-Type").is_none());
+        assert_eq!(payload["body"], "<h1>down</h1>");
     }
 
     #[test]
@@ -580,6 +3035,369 @@ mod tests {
         assert_eq!(parsed["body"], "injected");
     }
 
+    #[test]
+    fn test_corrupt_response_oversize_body() {
+        let flag = FlagValue {
+            enabled: true,
+            strategy: Some(CorruptionStrategy::Oversize),
+            response_size_bytes: Some(1000),
+            ..Default::default()
+        };
+        let body = r#"{"statusCode":200,"body":"{\"ok\":true}"}"#;
+        let result = corrupt_response(&flag, body);
+        assert!(result.len() > 1000);
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["statusCode"], 200);
+        let inner: serde_json::Value =
+            serde_json::from_str(parsed["body"].as_str().unwrap()).unwrap();
+        assert_eq!(inner["ok"], true);
+    }
+
+    #[test]
+    fn test_corrupt_response_oversize_default_exceeds_lambda_limit() {
+        let flag = FlagValue {
+            enabled: true,
+            strategy: Some(CorruptionStrategy::Oversize),
+            ..Default::default()
+        };
+        let result = corrupt_response(&flag, r#"{"body":"x"}"#);
+        assert!(result.len() > 6 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_corrupt_response_oversize_headers() {
+        let flag = FlagValue {
+            enabled: true,
+            strategy: Some(CorruptionStrategy::Oversize),
+            response_size_bytes: Some(20_000),
+            oversize_target: Some(OversizeTarget::Headers),
+            ..Default::default()
+        };
+        let body = r#"{"statusCode":200,"headers":{"Content-Type":"text/plain"},"body":"hi"}"#;
+        let result = corrupt_response(&flag, body);
+        assert!(result.len() > 20_000);
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["body"], "hi");
+        assert_eq!(parsed["headers"]["Content-Type"], "text/plain");
+        assert!(parsed["headers"]["x-failure-lambda-padding-0"].is_string());
+    }
+
+    #[test]
+    fn test_corrupt_response_oversize_non_object() {
+        let flag = FlagValue {
+            enabled: true,
+            strategy: Some(CorruptionStrategy::Oversize),
+            response_size_bytes: Some(100),
+            ..Default::default()
+        };
+        let result = corrupt_response(&flag, "[1,2,3]");
+        assert!(result.len() > 100);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&result).unwrap(),
+            serde_json::json!([1, 2, 3])
+        );
+
+        let result = corrupt_response(&flag, r#""plain""#);
+        assert!(result.len() > 100);
+        assert!(result.starts_with("\"plain "));
+    }
+
+    #[test]
+    fn test_corrupt_response_oversize_pretty_printed() {
+        let flag = FlagValue {
+            enabled: true,
+            strategy: Some(CorruptionStrategy::Oversize),
+            response_size_bytes: Some(100),
+            ..Default::default()
+        };
+        // Re-serialized compactly, so the whitespace must not count
+        let result = corrupt_response(&flag, "{\n  \"body\": \"x\"\n}");
+        assert_eq!(result.len(), 101);
+
+        let flag = FlagValue { oversize_target: Some(OversizeTarget::Headers), ..flag };
+        let result = corrupt_response(&flag, "{\n  \"body\": \"x\"\n}");
+        assert!(result.len() > 100);
+    }
+
+    #[test]
+    fn test_corrupt_response_oversize_already_large() {
+        let flag = FlagValue {
+            enabled: true,
+            strategy: Some(CorruptionStrategy::Oversize),
+            response_size_bytes: Some(4),
+            ..Default::default()
+        };
+        let body = r#"{"body":"already large"}"#;
+        assert_eq!(corrupt_response(&flag, body), body);
+    }
+
+    fn field_flag(strategy: CorruptionStrategy, paths: &[&str]) -> FlagValue {
+        FlagValue {
+            enabled: true,
+            strategy: Some(strategy),
+            paths: if paths.is_empty() {
+                None
+            } else {
+                Some(paths.iter().map(|p| p.to_string()).collect())
+            },
+            ..Default::default()
+        }
+    }
+
+    fn decoded_body(result: &str) -> serde_json::Value {
+        let parsed: serde_json::Value = serde_json::from_str(result).unwrap();
+        serde_json::from_str(parsed["body"].as_str().unwrap()).unwrap()
+    }
+
+    const API_RESPONSE: &str =
+        r#"{"statusCode":200,"body":"{\"id\":42,\"name\":\"widget\",\"tags\":[1,2,3,4,5],\"meta\":{\"v\":1}}"}"#;
+
+    #[test]
+    fn test_corrupt_fields_type_flip() {
+        let flag = field_flag(CorruptionStrategy::TypeFlip, &["body.id", "statusCode"]);
+        let result = corrupt_response(&flag, API_RESPONSE);
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["statusCode"], "200");
+        assert_eq!(decoded_body(&result)["id"], "42");
+    }
+
+    #[test]
+    fn test_corrupt_fields_null_field() {
+        let flag = field_flag(CorruptionStrategy::NullField, &["body.meta.v"]);
+        let result = corrupt_response(&flag, API_RESPONSE);
+        assert!(decoded_body(&result)["meta"]["v"].is_null());
+    }
+
+    #[test]
+    fn test_corrupt_fields_drop_key() {
+        let flag = field_flag(CorruptionStrategy::DropKey, &["body.name"]);
+        let result = corrupt_response(&flag, API_RESPONSE);
+        let body = decoded_body(&result);
+        assert!(body.get("name").is_none());
+        assert_eq!(body["id"], 42);
+    }
+
+    #[test]
+    fn test_corrupt_fields_drop_key_default_target() {
+        let flag = field_flag(CorruptionStrategy::DropKey, &[]);
+        let result = corrupt_response(&flag, API_RESPONSE);
+        // One key of the decoded body is removed; the response envelope is intact
+        assert_eq!(decoded_body(&result).as_object().unwrap().len(), 3);
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["statusCode"], 200);
+    }
+
+    #[test]
+    fn test_corrupt_fields_reorder_array() {
+        let flag = field_flag(CorruptionStrategy::ReorderArray, &[]);
+        let result = corrupt_response(&flag, API_RESPONSE);
+        let tags = decoded_body(&result)["tags"].clone();
+        assert_ne!(tags, serde_json::json!([1, 2, 3, 4, 5]));
+        let mut sorted: Vec<i64> = tags.as_array().unwrap().iter().map(|v| v.as_i64().unwrap()).collect();
+        sorted.sort();
+        assert_eq!(sorted, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_corrupt_fields_extra_field() {
+        let flag = field_flag(CorruptionStrategy::ExtraField, &["body.meta"]);
+        let result = corrupt_response(&flag, API_RESPONSE);
+        assert_eq!(decoded_body(&result)["meta"][EXTRA_FIELD_NAME]["injected"], true);
+
+        let flag = field_flag(CorruptionStrategy::ExtraField, &[]);
+        let result = corrupt_response(&flag, API_RESPONSE);
+        assert!(decoded_body(&result).get(EXTRA_FIELD_NAME).is_some());
+    }
+
+    #[test]
+    fn test_corrupt_fields_bit_flip() {
+        let flag = field_flag(CorruptionStrategy::BitFlip, &["body.name"]);
+        let result = corrupt_response(&flag, API_RESPONSE);
+        let name = decoded_body(&result)["name"].as_str().unwrap().to_string();
+        assert_eq!(name.len(), "widget".len());
+        assert_ne!(name, "widget");
+
+        // Without paths the encoded body string itself is altered
+        let flag = field_flag(CorruptionStrategy::BitFlip, &[]);
+        let result = corrupt_response(&flag, API_RESPONSE);
+        assert_ne!(result, API_RESPONSE);
+    }
+
+    #[test]
+    fn test_corrupt_fields_truncate_json() {
+        let flag = field_flag(CorruptionStrategy::TruncateJson, &[]);
+        let result = corrupt_response(&flag, API_RESPONSE);
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        let body = parsed["body"].as_str().unwrap();
+        assert!(serde_json::from_str::<serde_json::Value>(body).is_err());
+
+        // Without a body field, the whole response is truncated
+        let result = corrupt_response(&flag, r#"{"items":[1,2,3],"next":"abc"}"#);
+        assert!(serde_json::from_str::<serde_json::Value>(&result).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_fields_response_without_json_body() {
+        let flag = field_flag(CorruptionStrategy::NullField, &["count"]);
+        let result = corrupt_response(&flag, r#"{"count":3,"items":[]}"#);
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert!(parsed["count"].is_null());
+    }
+
+    #[test]
+    fn test_corrupt_fields_missing_path() {
+        for strategy in [CorruptionStrategy::TypeFlip, CorruptionStrategy::NullField] {
+            let flag = field_flag(strategy, &["body.missing", "body.absent.nested"]);
+            let result = corrupt_response(&flag, API_RESPONSE);
+            assert_eq!(decoded_body(&result), decoded_body(API_RESPONSE));
+        }
+    }
+
+    fn encode_body(text: &str, encoding: &ContentEncoding) -> String {
+        let response = serde_json::json!({ "body": text, "isBase64Encoded": false }).to_string();
+        let encoded = reencode_response_body(&response, encoding).unwrap();
+        serde_json::from_str::<serde_json::Value>(&encoded).unwrap()["body"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn encoded_response(text: &str, content_encoding: Option<&str>) -> String {
+        let encoding = match content_encoding {
+            Some("gzip") => ContentEncoding::Gzip,
+            Some("deflate") => ContentEncoding::Deflate,
+            Some("br") => ContentEncoding::Brotli,
+            _ => ContentEncoding::Identity,
+        };
+        let mut headers = serde_json::json!({ "Content-Type": "application/json" });
+        if let Some(ce) = content_encoding {
+            headers["Content-Encoding"] = serde_json::json!(ce);
+        }
+        serde_json::json!({
+            "statusCode": 200,
+            "headers": headers,
+            "isBase64Encoded": true,
+            "body": encode_body(text, &encoding)
+        })
+        .to_string()
+    }
+
+    fn decoded_text(result: &str) -> String {
+        let response: serde_json::Value = serde_json::from_str(result).unwrap();
+        let encoding = encoded_body(&response).unwrap();
+        let decoded = decode_response_body(&response, &encoding).unwrap();
+        serde_json::from_str::<serde_json::Value>(&decoded).unwrap()["body"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_encoded_body_roundtrip() {
+        for encoding in [None, Some("gzip"), Some("deflate"), Some("br")] {
+            let response = encoded_response(r#"{"id":1}"#, encoding);
+            assert_eq!(decoded_text(&response), r#"{"id":1}"#, "{encoding:?}");
+        }
+    }
+
+    #[test]
+    fn test_encoded_body_detection() {
+        let plain = serde_json::json!({ "body": "hello" });
+        assert!(encoded_body(&plain).is_none());
+
+        let multi = serde_json::json!({
+            "isBase64Encoded": true,
+            "body": "aGk=",
+            "multiValueHeaders": { "content-encoding": ["br"] }
+        });
+        assert_eq!(encoded_body(&multi), Some(ContentEncoding::Brotli));
+
+        let unsupported = serde_json::json!({
+            "isBase64Encoded": true,
+            "body": "aGk=",
+            "headers": { "Content-Encoding": "zstd" }
+        });
+        assert!(encoded_body(&unsupported).is_none());
+    }
+
+    #[test]
+    fn test_corrupt_response_gzip_body_field_strategy() {
+        let flag = field_flag(CorruptionStrategy::NullField, &["body.id"]);
+        let response = encoded_response(r#"{"id":42,"name":"widget"}"#, Some("gzip"));
+        let result = corrupt_response(&flag, &response);
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["isBase64Encoded"], true);
+        assert_eq!(parsed["headers"]["Content-Encoding"], "gzip");
+        let body: serde_json::Value = serde_json::from_str(&decoded_text(&result)).unwrap();
+        assert!(body["id"].is_null());
+        assert_eq!(body["name"], "widget");
+    }
+
+    #[test]
+    fn test_corrupt_response_brotli_mangle() {
+        let flag = FlagValue {
+            enabled: true,
+            ..Default::default()
+        };
+        let response = encoded_response("hello world this is a test message", Some("br"));
+        let result = corrupt_response(&flag, &response);
+        assert!(decoded_text(&result).contains('\u{FFFD}'));
+    }
+
+    #[test]
+    fn test_corrupt_response_base64_replace() {
+        let flag = FlagValue {
+            enabled: true,
+            body: Some("replaced".to_string()),
+            ..Default::default()
+        };
+        let response = encoded_response("original", None);
+        let result = corrupt_response(&flag, &response);
+        assert_eq!(decoded_text(&result), "replaced");
+    }
+
+    #[test]
+    fn test_corrupt_response_break_encoding_compressed() {
+        let flag = FlagValue {
+            enabled: true,
+            break_encoding: Some(true),
+            ..Default::default()
+        };
+        let response = encoded_response(&"payload ".repeat(100), Some("gzip"));
+        let result = corrupt_response(&flag, &response);
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        // Still valid base64, but the gzip stream is cut short
+        assert!(decode_response_body(&parsed, &ContentEncoding::Gzip).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_response_break_encoding_base64() {
+        let flag = FlagValue {
+            enabled: true,
+            break_encoding: Some(true),
+            ..Default::default()
+        };
+        let response = encoded_response("plain text", None);
+        let result = corrupt_response(&flag, &response);
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert!(parsed["body"].as_str().unwrap().contains('*'));
+        assert!(decode_response_body(&parsed, &ContentEncoding::Identity).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_response_oversize_base64_pads_headers() {
+        let flag = FlagValue {
+            enabled: true,
+            strategy: Some(CorruptionStrategy::Oversize),
+            response_size_bytes: Some(20_000),
+            ..Default::default()
+        };
+        let response = encoded_response("small", Some("gzip"));
+        let result = corrupt_response(&flag, &response);
+        assert!(result.len() > 20_000);
+        assert_eq!(decoded_text(&result), "small");
+    }
+
     #[test]
     fn test_mangle_string() {
         let input = "hello world this is a test message with enough characters";
@@ -596,10 +3414,184 @@ mod tests {
         assert_eq!(mangle_string(""), "");
     }
 
+    fn sqs_event(ids: &[&str]) -> serde_json::Value {
+        let records: Vec<serde_json::Value> = ids
+            .iter()
+            .map(|id| serde_json::json!({ "messageId": id, "eventSource": "aws:sqs" }))
+            .collect();
+        serde_json::json!({ "Records": records })
+    }
+
+    fn reported_ids(body: &str) -> Vec<String> {
+        let parsed: serde_json::Value = serde_json::from_str(body).unwrap();
+        parsed["batchItemFailures"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["itemIdentifier"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_item_identifiers() {
+        let kinesis = serde_json::json!({
+            "Records": [{ "kinesis": { "sequenceNumber": "4950" } }]
+        });
+        assert_eq!(batch_item_identifiers(&kinesis), vec!["4950"]);
+
+        let dynamodb = serde_json::json!({
+            "Records": [{ "dynamodb": { "SequenceNumber": "1110" } }]
+        });
+        assert_eq!(batch_item_identifiers(&dynamodb), vec!["1110"]);
+
+        assert!(batch_item_identifiers(&serde_json::json!({ "body": "x" })).is_empty());
+    }
+
+    #[test]
+    fn test_inject_batch_failures_count() {
+        let flag = FlagValue {
+            enabled: true,
+            batch_failure_count: Some(2),
+            ..Default::default()
+        };
+        let event = sqs_event(&["a", "b", "c", "d"]);
+        let result = inject_batch_failures(&flag, &event, "");
+        let ids = reported_ids(&result);
+        assert_eq!(ids.len(), 2);
+        // Failures keep event order
+        let positions: Vec<usize> = ids
+            .iter()
+            .map(|id| ["a", "b", "c", "d"].iter().position(|x| x == id).unwrap())
+            .collect();
+        assert!(positions[0] < positions[1]);
+    }
+
+    #[test]
+    fn test_inject_batch_failures_percentage() {
+        let flag = FlagValue {
+            enabled: true,
+            batch_failure_percentage: Some(25),
+            ..Default::default()
+        };
+        let event = sqs_event(&["a", "b", "c", "d", "e"]);
+        // 25% of 5 rounds up to 2
+        let result = inject_batch_failures(&flag, &event, "null");
+        assert_eq!(reported_ids(&result).len(), 2);
+    }
+
+    #[test]
+    fn test_inject_batch_failures_merges_existing() {
+        let flag = FlagValue {
+            enabled: true,
+            batch_failure_count: Some(3),
+            ..Default::default()
+        };
+        let event = sqs_event(&["a", "b", "c"]);
+        let body = r#"{"batchItemFailures":[{"itemIdentifier":"b"}]}"#;
+        let result = inject_batch_failures(&flag, &event, body);
+        let mut ids = reported_ids(&result);
+        ids.sort();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_inject_batch_failures_count_clamped() {
+        let flag = FlagValue {
+            enabled: true,
+            batch_failure_count: Some(10),
+            ..Default::default()
+        };
+        let event = sqs_event(&["a", "b"]);
+        let result = inject_batch_failures(&flag, &event, "{}");
+        assert_eq!(reported_ids(&result).len(), 2);
+    }
+
+    #[test]
+    fn test_inject_batch_failures_no_records() {
+        let flag = FlagValue {
+            enabled: true,
+            ..Default::default()
+        };
+        let body = r#"{"statusCode":200}"#;
+        let result = inject_batch_failures(&flag, &serde_json::json!({}), body);
+        assert_eq!(result, body);
+    }
+
     #[test]
     fn test_json_value_to_string() {
         assert_eq!(json_value_to_string(&serde_json::json!("hello")), "hello");
         assert_eq!(json_value_to_string(&serde_json::json!(42)), "42");
         assert_eq!(json_value_to_string(&serde_json::json!(true)), "true");
     }
+
+    fn template_context(event: &serde_json::Value) -> TemplateContext<'_> {
+        TemplateContext {
+            request_id: "req-123",
+            function_name: "orders-api".to_string(),
+            function_version: "$LATEST".to_string(),
+            deadline_ms: 1700000000000,
+            event,
+        }
+    }
+
+    #[test]
+    fn test_render_template_placeholders() {
+        let event = serde_json::json!({ "requestContext": { "requestId": "abc" }, "count": 3 });
+        let ctx = template_context(&event);
+        assert_eq!(
+            render_template(
+                "{mode} in {function_name}:{function_version} for {request_id} ({event:requestContext.requestId}, {event:count}) by {deadline_ms}",
+                "exception",
+                &ctx,
+            ),
+            "exception in orders-api:$LATEST for req-123 (abc, 3) by 1700000000000"
+        );
+        assert_eq!(render_template("[{event:missing.path}]", "exception", &ctx), "[]");
+    }
+
+    #[test]
+    fn test_render_template_keeps_other_braces() {
+        let event = serde_json::json!({});
+        let ctx = template_context(&event);
+        assert_eq!(
+            render_template(r#"{"error":"{request_id}","unknown":"{other}"}"#, "statuscode", &ctx),
+            r#"{"error":"req-123","unknown":"{other}"}"#
+        );
+        assert_eq!(render_template("{unclosed {mode}", "corruption", &ctx), "{unclosed corruption");
+    }
+
+    #[test]
+    fn test_render_flag_templates() {
+        let event = serde_json::json!({});
+        let ctx = template_context(&event);
+        let flag = FlagValue {
+            enabled: true,
+            exception_msg: Some("failed {request_id}".to_string()),
+            body: Some("{mode}".to_string()),
+            ..Default::default()
+        };
+        let rendered = render_flag_templates(&flag, "exception", &ctx);
+        assert_eq!(rendered.exception_msg.as_deref(), Some("failed req-123"));
+        assert_eq!(rendered.body.as_deref(), Some("exception"));
+        assert_eq!(flag.exception_msg.as_deref(), Some("failed {request_id}"));
+    }
+
+    #[test]
+    fn test_render_flag_templates_escapes_json_bodies() {
+        let event = serde_json::json!({ "headers": { "x": "say \"hi\"\\\n" }, "count": 3 });
+        let ctx = template_context(&event);
+        let flag = FlagValue {
+            enabled: true,
+            body: Some(r#"{"id":"{event:headers.x}","count":{event:count}}"#.to_string()),
+            ..Default::default()
+        };
+        let rendered = render_flag_templates(&flag, "statuscode", &ctx);
+        let body: serde_json::Value = serde_json::from_str(rendered.body.as_deref().unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({ "id": "say \"hi\"\\\n", "count": 3 }));
+
+        // Plain-text bodies are substituted as-is
+        let flag = FlagValue { body: Some("got {event:headers.x}".to_string()), ..flag };
+        let rendered = render_flag_templates(&flag, "statuscode", &ctx);
+        assert_eq!(rendered.body.as_deref(), Some("got say \"hi\"\\\n"));
+    }
 }
@@ -1,8 +1,10 @@
 mod config;
 mod failures;
+mod outbound;
 mod proxy;
 
 use std::env;
+use std::sync::Arc;
 use tracing::{info, error};
 
 #[tokio::main]
@@ -53,11 +55,48 @@ async fn main() {
     // is not affected by LD_PRELOAD, which is only set for the runtime process)
     let _ = config_manager.get_config().await;
 
+    // Optional outbound forward proxy. The wrapper exports HTTP_PROXY and
+    // HTTPS_PROXY for the runtime only if its readiness file exists, so a
+    // failed bind leaves the runtime's traffic direct. It binds before the
+    // Runtime API proxy signals readiness.
+    let outbound_rules = Arc::new(outbound::OutboundRules::default());
+    if env::var("FAILURE_OUTBOUND_PROXY").is_ok_and(|v| v == "true") {
+        let outbound_port: u16 = env::var("FAILURE_OUTBOUND_PROXY_PORT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(9010);
+        match outbound::bind(outbound_port).await {
+            Ok(listener) => {
+                info!(
+                    source = "failure-lambda",
+                    action = "startup",
+                    outbound_proxy_port = outbound_port,
+                );
+                tokio::spawn(outbound::serve(listener, outbound_rules.clone()));
+                if let Err(e) = tokio::fs::write("/tmp/.failure-lambda-outbound-ready", "").await {
+                    error!(
+                        source = "failure-lambda",
+                        action = "startup",
+                        message = format!("outbound proxy readiness file not written: {e}"),
+                    );
+                }
+            }
+            Err(e) => {
+                error!(
+                    source = "failure-lambda",
+                    action = "startup",
+                    message = format!("outbound proxy failed to bind: {e}"),
+                );
+            }
+        }
+    }
+
     // Start the HTTP proxy server (this blocks forever)
     if let Err(e) = proxy::start_proxy(
         proxy_port,
         original_runtime_api,
         config_manager,
+        outbound_rules,
     )
     .await
     {
@@ -0,0 +1,938 @@
+//! Outbound forward proxy for network faults at the connection level.
+//!
+//! When enabled, the wrapper points the runtime's `HTTP_PROXY`/`HTTPS_PROXY`
+//! at this listener. HTTPS traffic arrives as `CONNECT host:port` tunnels and
+//! plain HTTP as absolute-form requests (`GET http://host/path`). While an
+//! invocation has `outbound` rules active, connections to matching hosts are
+//! delayed, refused, reset mid-stream or throttled, and plain-HTTP requests
+//! matching an `outbound_responses` entry get a synthetic response instead of
+//! being forwarded; everything else is relayed untouched.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use hyper::StatusCode;
+use rand::Rng;
+use regex::Regex;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+use crate::config::{HttpErrorPreset, OutboundResponse, OutboundRule};
+
+/// Largest request head accepted from a client.
+const MAX_HEAD_BYTES: usize = 64 * 1024;
+const COPY_BUFFER_BYTES: usize = 16 * 1024;
+
+const BAD_REQUEST: &[u8] = b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const BAD_GATEWAY: &[u8] = b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const CONNECTION_ESTABLISHED: &[u8] = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+/// How long to wait for the client to finish sending a request that got a
+/// synthetic response, before closing the connection.
+const LINGER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Rules applied to new outbound connections, set by the Runtime API proxy
+/// for the duration of an invocation.
+#[derive(Default)]
+pub struct OutboundRules {
+    active: RwLock<ActiveRules>,
+    /// Bumped by every `set` and `clear`, so connections can tell when the
+    /// rules they were opened under no longer apply.
+    generation: AtomicU64,
+}
+
+#[derive(Default)]
+struct ActiveRules {
+    rules: Vec<(Regex, OutboundRule)>,
+    responses: Vec<CompiledResponse>,
+}
+
+/// An `outbound_responses` entry with its patterns compiled.
+struct CompiledResponse {
+    host: Option<Regex>,
+    method: Option<Regex>,
+    path: Option<Regex>,
+    response: OutboundResponse,
+}
+
+impl CompiledResponse {
+    /// Compile the entry's patterns. The method must match as a whole.
+    fn new(response: &OutboundResponse) -> Option<Self> {
+        let compile = |pattern: &Option<String>| pattern.as_deref().map(Regex::new).transpose();
+        let method = response.method.as_ref().map(|m| format!("^(?i:{m})$"));
+        Some(CompiledResponse {
+            host: compile(&response.host).ok()?,
+            method: compile(&method).ok()?,
+            path: compile(&response.path).ok()?,
+            response: response.clone(),
+        })
+    }
+
+    fn matches(&self, request: &ProxyRequest) -> bool {
+        let matches = |re: &Option<Regex>, value: &str| re.as_ref().is_none_or(|re| re.is_match(value));
+        matches(&self.host, &request.host)
+            && matches(&self.method, &request.method)
+            && matches(&self.path, &request.path)
+    }
+}
+
+impl OutboundRules {
+    /// Apply `rules` and `responses` to connections opened from now on.
+    /// Connections still open under earlier rules stop being throttled or
+    /// reset.
+    pub fn set(&self, rules: &[OutboundRule], responses: &[OutboundResponse]) {
+        let active = ActiveRules {
+            rules: rules
+                .iter()
+                .filter_map(|rule| Regex::new(&rule.host).ok().map(|re| (re, rule.clone())))
+                .collect(),
+            responses: responses.iter().filter_map(CompiledResponse::new).collect(),
+        };
+
+        info!(
+            source = "failure-lambda",
+            mode = "outbound",
+            action = "inject",
+            rule_count = active.rules.len(),
+            response_count = active.responses.len(),
+        );
+
+        let mut guard = self.active.write().unwrap_or_else(|e| e.into_inner());
+        *guard = active;
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Drop all rules, releasing connections still open under them.
+    pub fn clear(&self) {
+        let mut guard = self.active.write().unwrap_or_else(|e| e.into_inner());
+        *guard = ActiveRules::default();
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    /// The first rule matching `host` whose per-connection percentage roll
+    /// succeeds.
+    fn rule_for(&self, host: &str) -> Option<OutboundRule> {
+        let active = self.active.read().unwrap_or_else(|e| e.into_inner());
+        active
+            .rules
+            .iter()
+            .find(|(re, rule)| re.is_match(host) && roll(rule.percentage))
+            .map(|(_, rule)| rule.clone())
+    }
+
+    /// The first synthetic response matching a plain-HTTP request whose
+    /// percentage roll succeeds.
+    fn response_for(&self, request: &ProxyRequest) -> Option<OutboundResponse> {
+        let active = self.active.read().unwrap_or_else(|e| e.into_inner());
+        active
+            .responses
+            .iter()
+            .find(|compiled| compiled.matches(request) && roll(compiled.response.percentage))
+            .map(|compiled| compiled.response.clone())
+    }
+}
+
+/// Roll an entry's percentage. No percentage always applies.
+fn roll(percentage: Option<u32>) -> bool {
+    percentage.is_none_or(|pct| rand::thread_rng().gen_range(0..100) < pct)
+}
+
+/// Bind the outbound proxy listener on loopback.
+pub async fn bind(port: u16) -> io::Result<TcpListener> {
+    TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port))).await
+}
+
+/// Accept and relay outbound proxy connections until the process exits.
+pub async fn serve(listener: TcpListener, rules: Arc<OutboundRules>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                debug!(
+                    source = "failure-lambda",
+                    mode = "outbound",
+                    action = "proxy",
+                    message = format!("accept error: {e}"),
+                );
+                continue;
+            }
+        };
+        let rules = rules.clone();
+
+        tokio::task::spawn(async move {
+            if let Err(e) = handle_connection(stream, &rules).await {
+                debug!(
+                    source = "failure-lambda",
+                    mode = "outbound",
+                    action = "proxy",
+                    message = format!("connection error: {e}"),
+                );
+            }
+        });
+    }
+}
+
+/// Where a proxied request goes, and what to send upstream first.
+#[derive(Debug, PartialEq)]
+struct ProxyRequest {
+    host: String,
+    port: u16,
+    method: String,
+    /// Origin-form path with query; empty for `CONNECT`.
+    path: String,
+    /// `CONNECT` tunnel: answer 200, then relay bytes both ways.
+    tunnel: bool,
+    /// For plain HTTP, the request head rewritten to origin form.
+    head: Vec<u8>,
+}
+
+async fn handle_connection(mut client: TcpStream, rules: &OutboundRules) -> io::Result<()> {
+    let Some((head, buffered)) = read_head(&mut client).await? else {
+        return client.write_all(BAD_REQUEST).await;
+    };
+    let Some(request) = parse_request(&head) else {
+        return client.write_all(BAD_REQUEST).await;
+    };
+
+    if !request.tunnel {
+        if let Some(response) = rules.response_for(&request) {
+            let (status, payload) = synthetic_response(&response);
+            info!(
+                source = "failure-lambda",
+                mode = "outbound",
+                action = "respond",
+                host = %request.host,
+                method = %request.method,
+                path = %request.path,
+                status_code = status,
+            );
+            client.write_all(&payload).await?;
+            return lingering_close(client).await;
+        }
+    }
+
+    let generation = rules.generation();
+    let rule = rules.rule_for(&request.host);
+    if let Some(ref rule) = rule {
+        let delay_ms = sample_latency_ms(rule);
+        info!(
+            source = "failure-lambda",
+            mode = "outbound",
+            action = "match",
+            host = %request.host,
+            port = request.port,
+            latency_ms = delay_ms,
+            refuse = rule.refuse.unwrap_or(false),
+        );
+        if delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+        }
+        if rule.refuse == Some(true) {
+            return client.write_all(BAD_GATEWAY).await;
+        }
+    }
+
+    let mut upstream = match TcpStream::connect((request.host.as_str(), request.port)).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            debug!(
+                source = "failure-lambda",
+                mode = "outbound",
+                action = "proxy",
+                host = %request.host,
+                message = format!("upstream connect failed: {e}"),
+            );
+            return client.write_all(BAD_GATEWAY).await;
+        }
+    };
+
+    if request.tunnel {
+        client.write_all(CONNECTION_ESTABLISHED).await?;
+    } else {
+        upstream.write_all(&request.head).await?;
+    }
+    // Bytes the client sent after the head (a request body, or a pipelined
+    // TLS ClientHello) belong to the upstream stream
+    if !buffered.is_empty() {
+        upstream.write_all(&buffered).await?;
+    }
+
+    let lease = Lease { rules, generation };
+    relay(client, upstream, rule.as_ref(), &lease).await
+}
+
+/// Read up to the end of the request head. Returns the head and any bytes
+/// read past it, or None if the client closed or the head is too large.
+async fn read_head(client: &mut TcpStream) -> io::Result<Option<(String, Vec<u8>)>> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = client.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            return Ok(String::from_utf8(buf).ok().map(|head| (head, rest)));
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            return Ok(None);
+        }
+    }
+}
+
+/// Parse a proxy request head: `CONNECT host:port`, or an absolute-form
+/// `http://` request, whose head is rewritten to origin form with
+/// `Connection: close` so each client connection carries one request.
+fn parse_request(head: &str) -> Option<ProxyRequest> {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let (method, target, version) = (request_line.next()?, request_line.next()?, request_line.next()?);
+
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let (host, port) = split_host_port(target, 443)?;
+        return Some(ProxyRequest {
+            host,
+            port,
+            method: method.to_string(),
+            path: String::new(),
+            tunnel: true,
+            head: Vec::new(),
+        });
+    }
+
+    let scheme_end = target.find("://")?;
+    if !target[..scheme_end].eq_ignore_ascii_case("http") {
+        return None;
+    }
+    let rest = &target[scheme_end + 3..];
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = split_host_port(authority, 80)?;
+
+    let mut rewritten = format!("{method} {path} {version}\r\n");
+    for line in lines.filter(|line| !line.is_empty()) {
+        let name = line.split(':').next().unwrap_or_default().trim();
+        let hop_by_hop = ["proxy-connection", "proxy-authorization", "connection", "keep-alive"]
+            .iter()
+            .any(|h| name.eq_ignore_ascii_case(h));
+        if !hop_by_hop {
+            rewritten.push_str(line);
+            rewritten.push_str("\r\n");
+        }
+    }
+    rewritten.push_str("Connection: close\r\n\r\n");
+
+    Some(ProxyRequest {
+        host,
+        port,
+        method: method.to_string(),
+        path: path.to_string(),
+        tunnel: false,
+        head: rewritten.into_bytes(),
+    })
+}
+
+/// Build a synthetic response from a preset, overridden by the entry's own
+/// status, headers and body. Returns the status and the serialized response,
+/// which always closes the connection.
+fn synthetic_response(response: &OutboundResponse) -> (u16, Vec<u8>) {
+    let (status, mut headers, body): (u16, Vec<(String, String)>, String) = match response.preset {
+        Some(HttpErrorPreset::ServiceUnavailable) => (
+            503,
+            vec![("Retry-After".to_string(), "1".to_string())],
+            "Service Unavailable".to_string(),
+        ),
+        Some(HttpErrorPreset::TooManyRequests) => (
+            429,
+            vec![("Retry-After".to_string(), "1".to_string())],
+            "Too Many Requests".to_string(),
+        ),
+        Some(HttpErrorPreset::ThrottlingException) => (
+            400,
+            vec![
+                ("Content-Type".to_string(), "application/x-amz-json-1.0".to_string()),
+                ("x-amzn-ErrorType".to_string(), "ThrottlingException".to_string()),
+            ],
+            r#"{"__type":"ThrottlingException","message":"Rate exceeded"}"#.to_string(),
+        ),
+        None => (response.status_code.unwrap_or(500), Vec::new(), String::new()),
+    };
+    let status = response.status_code.unwrap_or(status);
+    // 204 and 304 never carry a body, not even a preset's
+    let bodiless = matches!(status, 204 | 304);
+    let body = if bodiless { String::new() } else { response.body.clone().unwrap_or(body) };
+
+    // Sorted so the output doesn't depend on HashMap order
+    let mut overrides: Vec<_> = response.headers.iter().flatten().collect();
+    overrides.sort();
+    for (name, value) in overrides {
+        headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        headers.push((name.clone(), value.clone()));
+    }
+    // Framing is ours: the body is sent whole and the connection closed
+    headers.retain(|(name, _)| {
+        !["content-length", "connection", "transfer-encoding"]
+            .iter()
+            .any(|h| name.eq_ignore_ascii_case(h))
+    });
+
+    let reason = StatusCode::from_u16(status)
+        .ok()
+        .and_then(|code| code.canonical_reason())
+        .unwrap_or("");
+    let mut head = format!("HTTP/1.1 {status} {reason}\r\n");
+    for (name, value) in &headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if !bodiless {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    let mut payload = head.into_bytes();
+    payload.extend_from_slice(body.as_bytes());
+    (status, payload)
+}
+
+/// Close after answering without reading the whole request: stop writing,
+/// then discard what the client still sends, so unread request bytes don't
+/// turn the close into a reset that could drop the response.
+async fn lingering_close(mut client: TcpStream) -> io::Result<()> {
+    client.shutdown().await?;
+    let mut buf = [0u8; 4096];
+    let _ = tokio::time::timeout(LINGER_TIMEOUT, async {
+        while let Ok(n) = client.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+        }
+    })
+    .await;
+    Ok(())
+}
+
+/// Split `host[:port]` or `[v6]:port`, falling back to `default_port`.
+fn split_host_port(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        let (host, after) = rest.split_once(']')?;
+        (host, after.strip_prefix(':'))
+    } else {
+        match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => default_port,
+    };
+    Some((host.to_string(), port))
+}
+
+fn sample_latency_ms(rule: &OutboundRule) -> u64 {
+    let Some(min) = rule.min_latency else {
+        return 0;
+    };
+    let max = rule.max_latency.unwrap_or(min).max(min);
+    rand::thread_rng().gen_range(min..=max).max(0.0).floor() as u64
+}
+
+/// The rules generation a connection's rule was picked from. Keep-alive
+/// tunnels outlive the invocation that opened them, so the rule's throttle and
+/// reset only hold until the rules next change.
+struct Lease<'a> {
+    rules: &'a OutboundRules,
+    generation: u64,
+}
+
+impl Lease<'_> {
+    fn expired(&self) -> bool {
+        self.rules.generation() != self.generation
+    }
+}
+
+/// How a relay direction ended.
+#[derive(Debug, PartialEq)]
+enum Pumped {
+    Eof,
+    /// `reset_after_bytes` were copied; the connection should be reset.
+    Reset,
+}
+
+/// Relay bytes between client and upstream until the upstream side finishes,
+/// applying the rule's bandwidth limit and mid-stream reset while `lease`
+/// holds.
+async fn relay(
+    mut client: TcpStream,
+    mut upstream: TcpStream,
+    rule: Option<&OutboundRule>,
+    lease: &Lease<'_>,
+) -> io::Result<()> {
+    let rate = rule.and_then(|r| r.bandwidth_bytes_per_second);
+    let reset_after = rule.and_then(|r| r.reset_after_bytes);
+
+    let outcome = {
+        let (mut client_read, mut client_write) = client.split();
+        let (mut upstream_read, mut upstream_write) = upstream.split();
+        let requests = pump(&mut client_read, &mut upstream_write, rate, None, lease);
+        let responses = pump(&mut upstream_read, &mut client_write, rate, reset_after, lease);
+        tokio::pin!(requests, responses);
+        tokio::select! {
+            outcome = &mut responses => outcome?,
+            outcome = &mut requests => {
+                outcome?;
+                responses.await?
+            }
+        }
+    };
+
+    if outcome == Pumped::Reset {
+        info!(
+            source = "failure-lambda",
+            mode = "outbound",
+            action = "reset",
+            bytes = reset_after.unwrap_or(0),
+        );
+        // A zero linger makes the close send RST instead of FIN. The
+        // deprecation is about non-zero lingers blocking on drop, which a
+        // zero linger never does.
+        #[allow(deprecated)]
+        client.set_linger(Some(Duration::ZERO))?;
+    }
+    Ok(())
+}
+
+/// Copy `reader` to `writer` until EOF, throttled to `rate` bytes per second.
+/// Stops with `Pumped::Reset` once `reset_after` bytes have been copied.
+/// Both stop applying once `lease` expires.
+async fn pump<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    mut rate: Option<u64>,
+    mut reset_after: Option<u64>,
+    lease: &Lease<'_>,
+) -> io::Result<Pumped>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Small chunks keep a throttled stream smooth (about 10 writes a second)
+    let mut buf = vec![0u8; rate.map_or(COPY_BUFFER_BYTES, |rate| (rate as usize / 10).clamp(1, COPY_BUFFER_BYTES))];
+    let started = Instant::now();
+    let mut total: u64 = 0;
+
+    loop {
+        if (rate.is_some() || reset_after.is_some()) && lease.expired() {
+            info!(
+                source = "failure-lambda",
+                mode = "outbound",
+                action = "release",
+                bytes = total,
+            );
+            rate = None;
+            reset_after = None;
+            buf.resize(COPY_BUFFER_BYTES, 0);
+        }
+        let chunk_size = buf.len();
+        let limit = match reset_after {
+            Some(reset_after) if total >= reset_after => return Ok(Pumped::Reset),
+            Some(reset_after) => chunk_size.min((reset_after - total) as usize),
+            None => chunk_size,
+        };
+        let n = reader.read(&mut buf[..limit]).await?;
+        if n == 0 {
+            let _ = writer.shutdown().await;
+            return Ok(Pumped::Eof);
+        }
+        writer.write_all(&buf[..n]).await?;
+        total += n as u64;
+
+        if let Some(rate) = rate {
+            let due = Duration::from_secs_f64(total as f64 / rate as f64);
+            let elapsed = started.elapsed();
+            if due > elapsed {
+                tokio::time::sleep(due - elapsed).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Start a TCP echo server, returning its port.
+    async fn echo_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let (mut read, mut write) = stream.split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+        port
+    }
+
+    /// Start the outbound proxy with `rules` active, returning its port.
+    async fn outbound_proxy(rules: &[OutboundRule]) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let active = Arc::new(OutboundRules::default());
+        active.set(rules, &[]);
+        tokio::spawn(serve(listener, active));
+        port
+    }
+
+    /// Open a CONNECT tunnel through the proxy, returning the stream and the
+    /// proxy's response line.
+    async fn connect_tunnel(proxy_port: u16, target_port: u16) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+        stream
+            .write_all(format!("CONNECT 127.0.0.1:{target_port} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        let (head, rest) = read_head(&mut stream).await.unwrap().unwrap();
+        assert!(rest.is_empty());
+        (stream, head.lines().next().unwrap_or_default().to_string())
+    }
+
+    #[test]
+    fn test_parse_connect() {
+        let request = parse_request("CONNECT dynamodb.us-east-1.amazonaws.com:443 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.host, "dynamodb.us-east-1.amazonaws.com");
+        assert_eq!(request.port, 443);
+        assert!(request.tunnel);
+    }
+
+    #[test]
+    fn test_parse_absolute_form() {
+        let head = "GET http://internal.example:8080/items?id=1 HTTP/1.1\r\nHost: internal.example:8080\r\nProxy-Connection: keep-alive\r\nAccept: */*\r\n\r\n";
+        let request = parse_request(head).unwrap();
+        assert_eq!(request.host, "internal.example");
+        assert_eq!(request.port, 8080);
+        assert!(!request.tunnel);
+        assert_eq!(
+            String::from_utf8(request.head).unwrap(),
+            "GET /items?id=1 HTTP/1.1\r\nHost: internal.example:8080\r\nAccept: */*\r\nConnection: close\r\n\r\n"
+        );
+
+        let request = parse_request("GET http://[::1] HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!((request.host.as_str(), request.port), ("::1", 80));
+        assert!(parse_request("GET /relative HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse_request("GET https://example.com/ HTTP/1.1\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("example.com", 80), Some(("example.com".to_string(), 80)));
+        assert_eq!(split_host_port("example.com:8443", 443), Some(("example.com".to_string(), 8443)));
+        assert_eq!(split_host_port("[2001:db8::1]:443", 80), Some(("2001:db8::1".to_string(), 443)));
+        assert_eq!(split_host_port(":80", 80), None);
+        assert_eq!(split_host_port("example.com:http", 80), None);
+    }
+
+    #[test]
+    fn test_rule_for_matches_host() {
+        let rules = OutboundRules::default();
+        rules.set(
+            &[
+                OutboundRule { host: "^s3\\.".to_string(), percentage: Some(0), ..Default::default() },
+                OutboundRule { host: "amazonaws\\.com$".to_string(), refuse: Some(true), ..Default::default() },
+            ],
+            &[],
+        );
+        // The 0% rule never applies, so the second rule decides
+        assert_eq!(rules.rule_for("s3.amazonaws.com").unwrap().refuse, Some(true));
+        assert!(rules.rule_for("example.com").is_none());
+        rules.clear();
+        assert!(rules.rule_for("s3.amazonaws.com").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_relays_bytes() {
+        let echo_port = echo_server().await;
+        let proxy_port = outbound_proxy(&[]).await;
+
+        let (mut stream, status) = connect_tunnel(proxy_port, echo_port).await;
+        assert_eq!(status, "HTTP/1.1 200 Connection Established");
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_refuse_answers_bad_gateway() {
+        let echo_port = echo_server().await;
+        let proxy_port = outbound_proxy(&[OutboundRule {
+            host: "^127\\.0\\.0\\.1$".to_string(),
+            refuse: Some(true),
+            ..Default::default()
+        }])
+        .await;
+
+        let (_, status) = connect_tunnel(proxy_port, echo_port).await;
+        assert_eq!(status, "HTTP/1.1 502 Bad Gateway");
+    }
+
+    #[tokio::test]
+    async fn test_latency_delays_connect() {
+        let echo_port = echo_server().await;
+        let proxy_port = outbound_proxy(&[OutboundRule {
+            host: ".".to_string(),
+            min_latency: Some(150.0),
+            ..Default::default()
+        }])
+        .await;
+
+        let started = Instant::now();
+        let (_, status) = connect_tunnel(proxy_port, echo_port).await;
+        assert_eq!(status, "HTTP/1.1 200 Connection Established");
+        assert!(started.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn test_reset_after_bytes() {
+        let echo_port = echo_server().await;
+        let proxy_port = outbound_proxy(&[OutboundRule {
+            host: ".".to_string(),
+            reset_after_bytes: Some(10),
+            ..Default::default()
+        }])
+        .await;
+
+        let (mut stream, _) = connect_tunnel(proxy_port, echo_port).await;
+        stream.write_all(&[b'x'; 64]).await.unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 64];
+        let error = loop {
+            match stream.read(&mut buf).await {
+                Ok(0) => panic!("connection closed without a reset"),
+                Ok(n) => received.extend_from_slice(&buf[..n]),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(received.len(), 10);
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth_throttles() {
+        let echo_port = echo_server().await;
+        let proxy_port = outbound_proxy(&[OutboundRule {
+            host: ".".to_string(),
+            bandwidth_bytes_per_second: Some(2000),
+            ..Default::default()
+        }])
+        .await;
+
+        let (mut stream, _) = connect_tunnel(proxy_port, echo_port).await;
+        let started = Instant::now();
+        stream.write_all(&[b'x'; 1000]).await.unwrap();
+        let mut buf = [0u8; 1000];
+        stream.read_exact(&mut buf).await.unwrap();
+        // 1000 bytes at 2000 B/s, throttled in both directions
+        assert!(started.elapsed() >= Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_clear_releases_open_tunnel() {
+        let echo_port = echo_server().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_port = listener.local_addr().unwrap().port();
+        let active = Arc::new(OutboundRules::default());
+        active.set(
+            &[OutboundRule {
+                host: ".".to_string(),
+                bandwidth_bytes_per_second: Some(2000),
+                ..Default::default()
+            }],
+            &[],
+        );
+        tokio::spawn(serve(listener, active.clone()));
+
+        let (mut stream, _) = connect_tunnel(proxy_port, echo_port).await;
+        let started = Instant::now();
+        stream.write_all(&[b'x'; 500]).await.unwrap();
+        let mut buf = vec![0u8; 500];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        // The same keep-alive tunnel runs at full speed once the rules are
+        // cleared; 64 KiB at 2000 B/s would take over 30 seconds
+        active.clear();
+        let started = Instant::now();
+        let payload = vec![b'y'; 64 * 1024];
+        let (mut read, mut write) = stream.split();
+        let (written, received) = tokio::join!(write.write_all(&payload), async {
+            let mut buf = vec![0u8; payload.len()];
+            read.read_exact(&mut buf).await.map(|_| buf)
+        });
+        written.unwrap();
+        assert_eq!(received.unwrap(), payload);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_plain_http_forwarding() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = listener.local_addr().unwrap().port();
+        let upstream = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (head, _) = read_head(&mut stream).await.unwrap().unwrap();
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+            head
+        });
+        let proxy_port = outbound_proxy(&[]).await;
+
+        let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+        stream
+            .write_all(format!("GET http://127.0.0.1:{upstream_port}/health HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert_eq!(response, "HTTP/1.1 204 No Content\r\n\r\n");
+        assert_eq!(
+            upstream.await.unwrap(),
+            "GET /health HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_response_for_matches_request() {
+        let rules = OutboundRules::default();
+        rules.set(
+            &[],
+            &[OutboundResponse {
+                host: Some("^sidecar$".to_string()),
+                method: Some("get|head".to_string()),
+                path: Some("^/items".to_string()),
+                preset: Some(HttpErrorPreset::TooManyRequests),
+                ..Default::default()
+            }],
+        );
+        let request = |method: &str, path: &str| ProxyRequest {
+            host: "sidecar".to_string(),
+            port: 80,
+            method: method.to_string(),
+            path: path.to_string(),
+            tunnel: false,
+            head: Vec::new(),
+        };
+        assert!(rules.response_for(&request("GET", "/items/1")).is_some());
+        // The method pattern is anchored, so GETX doesn't match
+        assert!(rules.response_for(&request("GETX", "/items/1")).is_none());
+        assert!(rules.response_for(&request("POST", "/items/1")).is_none());
+        assert!(rules.response_for(&request("GET", "/health")).is_none());
+    }
+
+    #[test]
+    fn test_synthetic_response_presets() {
+        let (status, payload) = synthetic_response(&OutboundResponse {
+            preset: Some(HttpErrorPreset::ServiceUnavailable),
+            ..Default::default()
+        });
+        assert_eq!(status, 503);
+        assert_eq!(
+            String::from_utf8(payload).unwrap(),
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nContent-Length: 19\r\nConnection: close\r\n\r\nService Unavailable"
+        );
+
+        let (status, payload) = synthetic_response(&OutboundResponse {
+            preset: Some(HttpErrorPreset::ThrottlingException),
+            ..Default::default()
+        });
+        let payload = String::from_utf8(payload).unwrap();
+        assert_eq!(status, 400);
+        assert!(payload.contains("x-amzn-ErrorType: ThrottlingException\r\n"));
+        assert!(payload.ends_with(r#"{"__type":"ThrottlingException","message":"Rate exceeded"}"#));
+    }
+
+    #[test]
+    fn test_synthetic_response_overrides() {
+        let (status, payload) = synthetic_response(&OutboundResponse {
+            preset: Some(HttpErrorPreset::TooManyRequests),
+            status_code: Some(503),
+            headers: Some(HashMap::from([
+                ("retry-after".to_string(), "30".to_string()),
+                ("Content-Length".to_string(), "999".to_string()),
+            ])),
+            body: Some("slow down".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(status, 503);
+        assert_eq!(
+            String::from_utf8(payload).unwrap(),
+            "HTTP/1.1 503 Service Unavailable\r\nretry-after: 30\r\nContent-Length: 9\r\nConnection: close\r\n\r\nslow down"
+        );
+    }
+
+    #[test]
+    fn test_synthetic_response_no_content() {
+        let (status, payload) = synthetic_response(&OutboundResponse {
+            preset: Some(HttpErrorPreset::ServiceUnavailable),
+            status_code: Some(204),
+            ..Default::default()
+        });
+        assert_eq!(status, 204);
+        assert_eq!(
+            String::from_utf8(payload).unwrap(),
+            "HTTP/1.1 204 No Content\r\nRetry-After: 1\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_synthetic_response_skips_upstream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = listener.local_addr().unwrap().port();
+        let proxy_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_port = proxy_listener.local_addr().unwrap().port();
+        let active = Arc::new(OutboundRules::default());
+        active.set(
+            &[],
+            &[OutboundResponse {
+                method: Some("POST".to_string()),
+                preset: Some(HttpErrorPreset::TooManyRequests),
+                ..Default::default()
+            }],
+        );
+        tokio::spawn(serve(proxy_listener, active));
+
+        let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+        stream
+            .write_all(
+                format!("POST http://127.0.0.1:{upstream_port}/items HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: 5\r\n\r\nhello")
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\n"));
+        assert!(response.ends_with("\r\n\r\nToo Many Requests"));
+
+        // The upstream never saw a connection
+        let accepted = tokio::time::timeout(Duration::from_millis(100), listener.accept()).await;
+        assert!(accepted.is_err());
+    }
+}
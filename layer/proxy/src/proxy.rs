@@ -14,12 +14,16 @@ use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 use tracing::{info, warn, error, debug};
 
-use crate::config::{ConfigManager, ResolvedFailure, resolve_failures};
-use crate::failures;
+use crate::config::{ConfigManager, FlagValue, ResolvedFailure, resolve_failures};
+use crate::failures::{self, StatusCodeInjection};
+use crate::outbound::OutboundRules;
 
 /// Path where the proxy writes denylist patterns for the LD_PRELOAD .so to read.
 const DENYLIST_FILE: &str = "/tmp/.failure-lambda-denylist";
 const DENYLIST_TMP: &str = "/tmp/.failure-lambda-denylist.tmp";
+/// Path where the proxy writes `connection` rules for the LD_PRELOAD .so.
+const CONNECT_FILE: &str = "/tmp/.failure-lambda-connect";
+const CONNECT_TMP: &str = "/tmp/.failure-lambda-connect.tmp";
 
 /// Per-invocation state carried from GET /next to POST /response|/error.
 struct InvocationState {
@@ -28,6 +32,20 @@ struct InvocationState {
     /// Whether denylist patterns were written for this invocation.
     /// Used to determine if the denylist file needs removing on cleanup.
     denylist_active: bool,
+    /// Whether `connection` rules were written for this invocation.
+    connect_active: bool,
+    /// Whether `outbound` rules were activated for this invocation.
+    outbound_active: bool,
+    /// Post-handler share of an injected latency, held before forwarding /response.
+    response_latency_ms: u64,
+    /// Whether `response_latency_ms` also applies to /error.
+    delay_errors: bool,
+    /// Deadline from /next, for `{deadline_ms}` in post-handler templates.
+    deadline_ms: u64,
+    /// Busy threads started by `cpu`, stopped when this state is dropped.
+    cpu_load: Option<failures::CpuLoad>,
+    /// Memory allocated by `memory`, released when this state is dropped.
+    memory: Option<Vec<u8>>,
 }
 
 /// Shared proxy state.
@@ -36,6 +54,10 @@ struct ProxyState {
     config_manager: ConfigManager,
     http_client: reqwest::Client,
     invocations: Mutex<HashMap<String, InvocationState>>,
+    /// Allocations kept across invocations by `memory` with `memory_leak`.
+    leaked_memory: std::sync::Mutex<Vec<Vec<u8>>>,
+    /// Rules applied by the outbound forward proxy, if it's running.
+    outbound: Arc<OutboundRules>,
 }
 
 /// Start the HTTP proxy server.
@@ -43,12 +65,15 @@ pub async fn start_proxy(
     listen_port: u16,
     original_runtime_api: String,
     config_manager: ConfigManager,
+    outbound: Arc<OutboundRules>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let state = Arc::new(ProxyState {
         original_runtime_api,
         config_manager,
         http_client: reqwest::Client::new(),
         invocations: Mutex::new(HashMap::new()),
+        leaked_memory: std::sync::Mutex::new(Vec::new()),
+        outbound,
     });
 
     let addr: SocketAddr = format!("127.0.0.1:{listen_port}").parse()?;
@@ -169,10 +194,14 @@ fn apply_headers(
 /// 1. Cleans up previous invocation's side effects
 /// 2. Forwards to real Runtime API to get next event
 /// 3. Fetches config and resolves failures
-/// 4. Executes pre-handler failures (latency, diskspace, denylist, timeout)
+/// 4. Executes pre-handler failures (latency, timeout, diskspace, cpu, memory,
+///    denylist, dns_latency, connection, outbound, event_mutation), keeping the
+///    post-handler share of split latency, then applies the collected DNS,
+///    connect and outbound rules
 /// 5. For terminating failures (exception, statuscode), consumes the invocation
 ///    and loops back to get the next one
-/// 6. Stores post-handler state (corruption) for the response phase
+/// 6. Stores post-handler state (corruption, batchfailure, post-phase statuscode
+///    and exception, response timeout) for the response phase
 /// 7. Returns the event to the runtime
 async fn handle_invocation_next(
     _req: Request<Incoming>,
@@ -192,10 +221,10 @@ async fn handle_invocation_next(
         {
             let mut invocations = state.invocations.lock().await;
             let had_denylist = invocations.values().any(|s| s.denylist_active);
+            let had_connect = invocations.values().any(|s| s.connect_active);
+            let had_outbound = invocations.values().any(|s| s.outbound_active);
             invocations.clear();
-            if had_denylist {
-                remove_denylist();
-            }
+            cleanup_interception(state, had_denylist, had_connect, had_outbound);
         }
 
         // Forward to real Runtime API
@@ -255,10 +284,24 @@ async fn handle_invocation_next(
         let mut should_short_circuit = false;
         let mut post_handler_failures = Vec::new();
         let mut denylist_active = false;
+        // Rules from denylist and dns_latency, written to one file for the .so
+        let mut dns_rules: Vec<String> = Vec::new();
+        let mut connect_active = false;
+        let mut connect_rules: Vec<String> = Vec::new();
+        let mut outbound_active = false;
+        let mut outbound_rules = Vec::new();
+        let mut outbound_responses = Vec::new();
+        let mut response_latency_ms = 0;
+        let mut delay_errors = false;
+        let mut cpu_load = None;
+        let mut memory = None;
+        let mut memory_injected = false;
+        // Mutations apply to a copy so match conditions keep seeing the original event
+        let mut mutated_event: Option<serde_json::Value> = None;
 
         for failure in &resolved_failures {
-            // Skip corruption — it's post-handler
-            if failure.mode == "corruption" {
+            // Skip post-handler failures (corruption, batchfailure)
+            if failure.is_post_handler() {
                 post_handler_failures.push(failure.clone());
                 continue;
             }
@@ -278,7 +321,8 @@ async fn handle_invocation_next(
 
             match failure.mode.as_str() {
                 "latency" => {
-                    failures::inject_latency(&failure.flag).await;
+                    response_latency_ms = failures::inject_latency(&failure.flag).await;
+                    delay_errors = failure.flag.delay_errors.unwrap_or(false);
                 }
                 "timeout" => {
                     failures::inject_timeout(deadline_ms, &failure.flag).await;
@@ -291,39 +335,66 @@ async fn handle_invocation_next(
                     .await
                     .ok();
                 }
+                "cpu" => {
+                    cpu_load = Some(failures::inject_cpu(&failure.flag));
+                }
+                "memory" => {
+                    memory_injected = true;
+                    let flag = failure.flag.clone();
+                    let state = Arc::clone(state);
+                    memory = tokio::task::spawn_blocking(move || {
+                        let mut leaked = state
+                            .leaked_memory
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner());
+                        failures::inject_memory(&flag, &mut leaked)
+                    })
+                    .await
+                    .ok()
+                    .flatten();
+                }
                 "denylist" => {
-                    if let Some(ref patterns) = failure.flag.deny_list {
-                        match write_denylist(patterns) {
-                            Ok(()) => {
-                                info!(
-                                    source = "failure-lambda",
-                                    mode = "denylist",
-                                    action = "inject",
-                                    pattern_count = patterns.len(),
-                                );
-                                denylist_active = true;
-                            }
-                            Err(e) => {
-                                error!(
-                                    source = "failure-lambda",
-                                    mode = "denylist",
-                                    action = "error",
-                                    message = format!("failed to write denylist file: {e}"),
-                                );
-                            }
-                        }
+                    dns_rules.extend(failures::denylist_rules(
+                        &failure.flag,
+                        &state.original_runtime_api,
+                    ));
+                }
+                "dns_latency" => {
+                    dns_rules.extend(failures::dns_latency_rules(&failure.flag));
+                }
+                "connection" => {
+                    connect_rules.extend(failures::connection_rules(&failure.flag));
+                }
+                "outbound" => {
+                    outbound_rules.extend(failure.flag.outbound_rules.iter().flatten().cloned());
+                    outbound_responses.extend(failure.flag.outbound_responses.iter().flatten().cloned());
+                }
+                "event_mutation" => {
+                    let source = mutated_event.as_ref().unwrap_or(&event);
+                    if let Some(mutated) = failures::mutate_event(&failure.flag, source) {
+                        mutated_event = Some(mutated);
                     }
                 }
                 "statuscode" => {
                     // Terminating: post response to real API and loop for next invocation
-                    let payload = failures::build_statuscode_payload(&failure.flag);
-                    let body_str = serde_json::to_string(&payload).unwrap_or_default();
+                    let ctx = failures::TemplateContext::new(&request_id, deadline_ms, &event);
+                    let flag = failures::render_flag_templates(&failure.flag, &failure.mode, &ctx);
+                    let (endpoint, body_str, headers) = match failures::build_statuscode_injection(&flag, &event) {
+                        StatusCodeInjection::Response(payload) => {
+                            ("response", serde_json::to_string(&payload).unwrap_or_default(), Vec::new())
+                        }
+                        StatusCodeInjection::Error(error_flag) => {
+                            let (body_str, headers) = build_exception_error(&error_flag);
+                            ("error", body_str, headers)
+                        }
+                    };
                     post_to_runtime_api(
                         &state.http_client,
                         &state.original_runtime_api,
                         &request_id,
-                        "response",
+                        endpoint,
                         &body_str,
+                        &headers,
                     )
                     .await?;
                     should_short_circuit = true;
@@ -331,14 +402,16 @@ async fn handle_invocation_next(
                 }
                 "exception" => {
                     // Terminating: post error to real API and loop for next invocation
-                    let payload = failures::build_exception_payload(&failure.flag);
-                    let body_str = serde_json::to_string(&payload).unwrap_or_default();
+                    let ctx = failures::TemplateContext::new(&request_id, deadline_ms, &event);
+                    let flag = failures::render_flag_templates(&failure.flag, &failure.mode, &ctx);
+                    let (body_str, headers) = build_exception_error(&flag);
                     post_to_runtime_api(
                         &state.http_client,
                         &state.original_runtime_api,
                         &request_id,
                         "error",
                         &body_str,
+                        &headers,
                     )
                     .await?;
                     should_short_circuit = true;
@@ -348,17 +421,61 @@ async fn handle_invocation_next(
             }
         }
 
+        // Memory leaked by earlier invocations is still held when the memory
+        // flag didn't fire this time
+        if !memory_injected {
+            let leaked = state.leaked_memory.lock().unwrap_or_else(|e| e.into_inner());
+            failures::report_leaked_memory(&leaked);
+        }
+
+        // A short-circuited invocation never reaches the handler, so its DNS,
+        // connect and outbound rules are never applied
         if should_short_circuit {
-            // Clean up denylist immediately if it was activated during this
-            // short-circuited invocation (no /response handler will run).
-            if denylist_active {
-                remove_denylist();
-            }
             continue;
         }
 
+        if !dns_rules.is_empty() {
+            match write_rules_file(DENYLIST_FILE, DENYLIST_TMP, &dns_rules) {
+                Ok(()) => denylist_active = true,
+                Err(e) => {
+                    error!(
+                        source = "failure-lambda",
+                        mode = "denylist",
+                        action = "error",
+                        message = format!("failed to write denylist file: {e}"),
+                    );
+                }
+            }
+        }
+
+        if !connect_rules.is_empty() {
+            match write_rules_file(CONNECT_FILE, CONNECT_TMP, &connect_rules) {
+                Ok(()) => connect_active = true,
+                Err(e) => {
+                    error!(
+                        source = "failure-lambda",
+                        mode = "connection",
+                        action = "error",
+                        message = format!("failed to write connect rules file: {e}"),
+                    );
+                }
+            }
+        }
+
+        if !outbound_rules.is_empty() || !outbound_responses.is_empty() {
+            state.outbound.set(&outbound_rules, &outbound_responses);
+            outbound_active = true;
+        }
+
         // Store per-invocation state for the response/error phase
-        if !post_handler_failures.is_empty() || denylist_active {
+        if !post_handler_failures.is_empty()
+            || denylist_active
+            || connect_active
+            || outbound_active
+            || response_latency_ms > 0
+            || cpu_load.is_some()
+            || memory.is_some()
+        {
             let mut invocations = state.invocations.lock().await;
             invocations.insert(
                 request_id.clone(),
@@ -366,11 +483,21 @@ async fn handle_invocation_next(
                     failures: post_handler_failures,
                     event: event.clone(),
                     denylist_active,
+                    connect_active,
+                    outbound_active,
+                    response_latency_ms,
+                    delay_errors,
+                    deadline_ms,
+                    cpu_load,
+                    memory,
                 },
             );
         }
 
-        // Return event to runtime
+        // Return event to runtime, re-serialized if event_mutation changed it
+        if let Some(mutated) = mutated_event {
+            return build_proxy_response(&serde_json::to_vec(&mutated)?, &response_headers);
+        }
         return build_proxy_response(&event_body, &response_headers);
     }
 }
@@ -387,24 +514,29 @@ async fn handle_invocation_response(
     // Read response body from runtime as raw bytes
     let body_bytes = req.collect().await?.to_bytes();
 
-    // Remove per-invocation state (corruption + cleanup info)
+    // Remove per-invocation state (post-handler failures + cleanup info)
     let invocation_state = {
         let mut invocations = state.invocations.lock().await;
         invocations.remove(&request_id)
     };
 
-    // Apply corruption if active, otherwise forward raw bytes untouched.
-    // Note: corruption match conditions are evaluated against the incoming Lambda
+    // Apply post-handler failures if active, otherwise forward raw bytes untouched.
+    // Note: post-handler match conditions are evaluated against the incoming Lambda
     // event (from /next), not the function's response. This is by design — you
     // target failures based on what triggered the invocation, consistent with how
     // all other failure modes work.
-    let (final_body, denylist_was_active) = match invocation_state {
+    let mut injected_error = None;
+    let mut response_timeout_deadline = None;
+    let (final_body, denylist_was_active, connect_was_active, outbound_was_active, response_latency_ms) = match invocation_state {
         Some(inv_state) => {
+            // The handler is done; release its resource pressure before the
+            // rest of the response path
+            drop(inv_state.cpu_load);
+            drop(inv_state.memory);
             let mut body = body_bytes;
+            let ctx =
+                failures::TemplateContext::new(&request_id, inv_state.deadline_ms, &inv_state.event);
             for failure in &inv_state.failures {
-                if failure.mode != "corruption" {
-                    continue;
-                }
                 if let Some(ref conditions) = failure.flag.match_conditions {
                     if !failures::matches_conditions(&inv_state.event, conditions) {
                         continue;
@@ -414,37 +546,70 @@ async fn handle_invocation_response(
                 if roll >= failure.percentage as f64 {
                     continue;
                 }
-                // Corruption requires the body as a UTF-8 string
-                match std::str::from_utf8(&body) {
-                    Ok(body_str) => {
-                        body =
-                            Bytes::from(failures::corrupt_response(&failure.flag, body_str));
-                    }
-                    Err(_) => {
-                        warn!(
-                            source = "failure-lambda",
-                            mode = "corruption",
-                            message = "response body is not valid UTF-8; skipping corruption",
-                        );
+                // Response timeout: hold the response once the body is final
+                if failure.mode == "timeout" {
+                    response_timeout_deadline = Some(inv_state.deadline_ms);
+                    continue;
+                }
+                let flag = failures::render_flag_templates(&failure.flag, &failure.mode, &ctx);
+                // Post-phase exception: discard the handler's response and report
+                // an error instead. Nothing after it can affect the outcome.
+                if failure.mode == "exception" {
+                    injected_error = Some(build_exception_error(&flag));
+                    break;
+                }
+                // The handler has already run (and committed its side effects); the
+                // caller only sees the injected status code, or for AppSync an error.
+                if failure.mode == "statuscode" {
+                    match failures::build_statuscode_injection(&flag, &inv_state.event) {
+                        StatusCodeInjection::Response(payload) => {
+                            body = Bytes::from(payload.to_string());
+                            continue;
+                        }
+                        StatusCodeInjection::Error(error_flag) => {
+                            injected_error = Some(build_exception_error(&error_flag));
+                            break;
+                        }
                     }
                 }
+                body = apply_post_handler_failure(&failure.mode, &flag, &inv_state.event, body);
             }
-            (body, inv_state.denylist_active)
+            (
+                body,
+                inv_state.denylist_active,
+                inv_state.connect_active,
+                inv_state.outbound_active,
+                inv_state.response_latency_ms,
+            )
         }
-        None => (body_bytes, false),
+        None => (body_bytes, false, false, false, 0),
     };
 
     // Cleanup based on per-invocation state
-    cleanup_denylist(denylist_was_active);
+    cleanup_interception(state, denylist_was_active, connect_was_active, outbound_was_active);
+
+    // Hold the response for the post-handler share of any injected latency
+    failures::inject_response_latency(response_latency_ms, "response").await;
+
+    // Hold the response past the deadline so Lambda times the invocation out
+    if let Some(deadline_ms) = response_timeout_deadline {
+        failures::inject_response_timeout(deadline_ms).await;
+    }
+
+    // Forward to real API with the runtime's original request headers, or
+    // report the injected error in place of the response
+    let (endpoint, headers, final_body) = match injected_error {
+        Some((payload, headers)) => ("error", headers, Bytes::from(payload)),
+        None => ("response", forward_headers, final_body),
+    };
 
-    // Forward to real API with the runtime's original request headers
     let upstream_url = format!(
-        "http://{}/2018-06-01/runtime/invocation/{}/response",
-        state.original_runtime_api, request_id
+        "http://{}/2018-06-01/runtime/invocation/{}/{}",
+        state.original_runtime_api, request_id, endpoint
     );
 
     let builder = state.http_client.post(&upstream_url);
-    let upstream_response = apply_headers(builder, &forward_headers)
+    let upstream_response = apply_headers(builder, &headers)
         .body(final_body.to_vec())
         .send()
         .await?;
@@ -458,6 +623,33 @@ async fn handle_invocation_response(
         .unwrap())
 }
 
+/// Apply a single post-handler failure to the runtime's response body.
+fn apply_post_handler_failure(
+    mode: &str,
+    flag: &FlagValue,
+    event: &serde_json::Value,
+    body: Bytes,
+) -> Bytes {
+    // Remaining post-handler failures require the body as a UTF-8 string
+    let body_str = match std::str::from_utf8(&body) {
+        Ok(body_str) => body_str,
+        Err(_) => {
+            warn!(
+                source = "failure-lambda",
+                mode = mode,
+                message = "response body is not valid UTF-8; skipping",
+            );
+            return body;
+        }
+    };
+
+    match mode {
+        "corruption" => Bytes::from(failures::corrupt_response(flag, body_str)),
+        "batchfailure" => Bytes::from(failures::inject_batch_failures(flag, event, body_str)),
+        _ => body,
+    }
+}
+
 /// Handle POST /runtime/invocation/{id}/error
 async fn handle_invocation_error(
     req: Request<Incoming>,
@@ -471,15 +663,20 @@ async fn handle_invocation_error(
     let body_bytes = req.collect().await?.to_bytes();
 
     // Remove invocation state and extract cleanup info
-    let denylist_was_active = {
+    let (denylist_was_active, connect_was_active, outbound_was_active, error_latency_ms) = {
         let mut invocations = state.invocations.lock().await;
-        invocations
-            .remove(&request_id)
-            .map_or(false, |s| s.denylist_active)
+        invocations.remove(&request_id).map_or((false, false, false, 0), |s| {
+            drop(s.cpu_load);
+            drop(s.memory);
+            let latency_ms = if s.delay_errors { s.response_latency_ms } else { 0 };
+            (s.denylist_active, s.connect_active, s.outbound_active, latency_ms)
+        })
     };
 
     // Cleanup based on per-invocation state
-    cleanup_denylist(denylist_was_active);
+    cleanup_interception(state, denylist_was_active, connect_was_active, outbound_was_active);
+
+    failures::inject_response_latency(error_latency_ms, "error").await;
 
     let upstream_url = format!(
         "http://{}/2018-06-01/runtime/invocation/{}/error",
@@ -532,48 +729,84 @@ async fn passthrough(
         .unwrap())
 }
 
-/// Write deny patterns to the denylist file atomically (write to tmp, then rename).
-/// The LD_PRELOAD .so reads this file on each getaddrinfo() call.
-fn write_denylist(patterns: &[String]) -> std::io::Result<()> {
+/// Write intercept rules to `path` atomically (write to `tmp`, then rename),
+/// one rule per line. The LD_PRELOAD .so reads the denylist file on each
+/// getaddrinfo() call and the connect file on each connect() call.
+fn write_rules_file(path: &str, tmp: &str, rules: &[String]) -> std::io::Result<()> {
     use std::io::Write;
-    let mut f = std::fs::File::create(DENYLIST_TMP)?;
-    for pattern in patterns {
-        writeln!(f, "{}", pattern)?;
+    let mut f = std::fs::File::create(tmp)?;
+    for rule in rules {
+        writeln!(f, "{}", rule)?;
     }
     f.sync_all()?;
-    std::fs::rename(DENYLIST_TMP, DENYLIST_FILE)?;
+    std::fs::rename(tmp, path)?;
     Ok(())
 }
 
-/// Remove the denylist file. No-op if it doesn't exist.
-fn remove_denylist() {
-    let _ = std::fs::remove_file(DENYLIST_FILE);
-    let _ = std::fs::remove_file(DENYLIST_TMP);
+/// Remove a rules file. No-op if it doesn't exist.
+fn remove_rules_file(path: &str, tmp: &str) {
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(tmp);
 }
 
-/// Remove denylist file if it was activated during this invocation.
-fn cleanup_denylist(denylist_was_active: bool) {
+/// Deactivate the network interception that was activated during this
+/// invocation: remove its rules files and clear the outbound proxy rules.
+fn cleanup_interception(
+    state: &ProxyState,
+    denylist_was_active: bool,
+    connect_was_active: bool,
+    outbound_was_active: bool,
+) {
     if denylist_was_active {
-        remove_denylist();
+        remove_rules_file(DENYLIST_FILE, DENYLIST_TMP);
+    }
+    if connect_was_active {
+        remove_rules_file(CONNECT_FILE, CONNECT_TMP);
     }
+    if outbound_was_active {
+        state.outbound.clear();
+    }
+}
+
+/// Build the body and headers for an injected exception posted to /error.
+/// The error-type and X-Ray headers let Lambda report the error the same way
+/// it would for one thrown by the handler.
+fn build_exception_error(flag: &FlagValue) -> (String, Vec<(String, String)>) {
+    let payload = failures::build_exception_payload(flag);
+    let headers = vec![
+        ("content-type".to_string(), "application/json".to_string()),
+        (
+            "lambda-runtime-function-error-type".to_string(),
+            failures::exception_error_type(flag).to_string(),
+        ),
+        (
+            "lambda-runtime-function-xray-error-cause".to_string(),
+            failures::build_xray_error_cause(&payload),
+        ),
+    ];
+    (payload.to_string(), headers)
 }
 
 /// Post to the real Runtime API (for exception/statuscode short-circuits).
+/// `headers` are added after the default JSON content type and may replace it.
 async fn post_to_runtime_api(
     client: &reqwest::Client,
     original_runtime_api: &str,
     request_id: &str,
     endpoint: &str,
     body: &str,
+    headers: &[(String, String)],
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let url = format!(
         "http://{}/2018-06-01/runtime/invocation/{}/{}",
         original_runtime_api, request_id, endpoint
     );
 
-    let response = client
-        .post(&url)
-        .header("Content-Type", "application/json")
+    let mut builder = client.post(&url);
+    if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("content-type")) {
+        builder = builder.header("Content-Type", "application/json");
+    }
+    let response = apply_headers(builder, headers)
         .body(body.to_string())
         .send()
         .await?;
@@ -604,13 +837,18 @@ fn extract_request_id_from_path(path: &str) -> String {
     }
 }
 
-/// Build a proxy response from upstream bytes and headers.
+/// Build a proxy response from upstream bytes and headers. The upstream
+/// `content-length` is dropped since event_mutation may change the body size;
+/// hyper sets it from the body instead.
 fn build_proxy_response(
     body: &[u8],
     headers: &[(String, String)],
 ) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error + Send + Sync>> {
     let mut builder = Response::builder().status(200);
     for (name, value) in headers {
+        if name.eq_ignore_ascii_case("content-length") {
+            continue;
+        }
         builder = builder.header(name.as_str(), value.as_str());
     }
     Ok(builder.body(Full::new(Bytes::copy_from_slice(body)))?)
@@ -645,4 +883,11 @@ mod tests {
         let response = build_proxy_response(body, &headers).unwrap();
         assert_eq!(response.status(), 200);
     }
+
+    #[test]
+    fn test_build_proxy_response_drops_upstream_content_length() {
+        let headers = vec![("content-length".to_string(), "999".to_string())];
+        let response = build_proxy_response(b"{}", &headers).unwrap();
+        assert!(response.headers().get("content-length").is_none());
+    }
 }